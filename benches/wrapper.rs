@@ -0,0 +1,228 @@
+//! Measures the overhead of the wrapper over raw emulation: acting, screen,
+//! RAM and action set fetches with and without allocating, cloning and
+//! restoring states, and serialization.
+//!
+//! ```text
+//! ALE_BENCH_ROM=roms/pong.bin cargo bench --bench wrapper [-- FILTER]
+//! ```
+//!
+//! Like Criterion, every benchmark is warmed up and then timed over a number
+//! of samples, each running enough iterations to take about 10 ms. Only
+//! benchmarks whose name contains `FILTER` are run. Results are printed and
+//! written as JSON to `ALE_BENCH_JSON`, `target/ale-bench.json` by default,
+//! so they can be compared between commits.
+//!
+//! There is no mock of libale_c, so the benchmarks always run against the
+//! real library and are skipped when `ALE_BENCH_ROM` isn't set. The `raw_`
+//! benchmarks call `ale::ffi` directly, as a baseline for the wrapper.
+extern crate ale;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use std::time::{Duration,Instant};
+use ale::{Action,Ale,AleState,Game};
+
+const WARM_UP: Duration = Duration::from_millis(500);
+const SAMPLE_TIME: Duration = Duration::from_millis(10);
+const SAMPLES: usize = 50;
+
+/// Keeps the optimizer from removing a benchmark's work.
+fn black_box<T>(value: T) -> T {
+    unsafe {
+        let copy = ::std::ptr::read_volatile(&value);
+        ::std::mem::forget(value);
+        copy
+    }
+}
+
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+struct Summary {
+    name: &'static str,
+    iterations: u64,
+    /// Nanoseconds per iteration of each sample.
+    samples: Vec<f64>,
+}
+
+impl Summary {
+    fn mean(&self) -> f64 {
+        self.samples.iter().sum::<f64>() / self.samples.len() as f64
+    }
+
+    fn median(&self) -> f64 {
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mid = sorted.len() / 2;
+        if sorted.len() % 2 == 0 { (sorted[mid - 1] + sorted[mid]) / 2.0 } else { sorted[mid] }
+    }
+
+    fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        let var = self.samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / self.samples.len() as f64;
+        var.sqrt()
+    }
+
+    fn min(&self) -> f64 {
+        self.samples.iter().cloned().fold(::std::f64::INFINITY, f64::min)
+    }
+}
+
+struct Bencher<'a> {
+    game: &'a mut Game,
+    filter: Option<String>,
+    results: Vec<Summary>,
+}
+
+impl<'a> Bencher<'a> {
+    /// Times `f`, which runs one iteration against the game.
+    fn bench<F: FnMut(&mut Game)>(&mut self, name: &'static str, mut f: F) {
+        if let Some(ref filter) = self.filter {
+            if !name.contains(&**filter) {
+                return;
+            }
+        }
+
+        // Warm up, doubling the iterations per batch to estimate their cost.
+        let mut iterations = 1u64;
+        let mut elapsed = Duration::from_secs(0);
+        let mut done = 0u64;
+        let start = Instant::now();
+        while start.elapsed() < WARM_UP {
+            let batch = Instant::now();
+            for _ in 0..iterations {
+                f(self.game);
+            }
+            elapsed += batch.elapsed();
+            done += iterations;
+            iterations *= 2;
+        }
+        let per_iteration = secs(elapsed) / done as f64;
+        let iterations = ::std::cmp::max(1, (secs(SAMPLE_TIME) / per_iteration) as u64);
+
+        let mut samples = Vec::with_capacity(SAMPLES);
+        for _ in 0..SAMPLES {
+            let sample = Instant::now();
+            for _ in 0..iterations {
+                f(self.game);
+            }
+            samples.push(secs(sample.elapsed()) * 1e9 / iterations as f64);
+        }
+
+        let summary = Summary { name: name, iterations: iterations, samples: samples };
+        println!("{:<24} {:>12.1} ns/iter (median {:.1}, +/- {:.1})",
+                 name, summary.mean(), summary.median(), summary.std_dev());
+        self.results.push(summary);
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+fn write_json(path: &Path, rom: &str, results: &[Summary]) -> ::std::io::Result<()> {
+    let mut out = try!(File::create(path));
+    try!(writeln!(out, "{{"));
+    try!(writeln!(out, "  \"rom\": {},", json_string(rom)));
+    try!(writeln!(out, "  \"benchmarks\": ["));
+    for (i, s) in results.iter().enumerate() {
+        try!(writeln!(out, "    {{\"name\": {}, \"iterations\": {}, \"samples\": {}, \"mean_ns\": {:.3}, \"median_ns\": {:.3}, \"std_dev_ns\": {:.3}, \"min_ns\": {:.3}}}{}",
+                      json_string(s.name), s.iterations, s.samples.len(), s.mean(), s.median(), s.std_dev(), s.min(),
+                      if i + 1 < results.len() { "," } else { "" }));
+    }
+    try!(writeln!(out, "  ]"));
+    try!(writeln!(out, "}}"));
+
+    Ok(())
+}
+
+fn main() {
+    let rom = match env::var("ALE_BENCH_ROM") {
+        Ok(rom) => rom,
+        Err(_) => {
+            println!("ALE_BENCH_ROM is not set, skipping the wrapper benchmarks");
+            return;
+        },
+    };
+    // Cargo passes `--bench`, anything else is a filter.
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let json = env::var("ALE_BENCH_JSON").map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target").join("ale-bench.json"));
+
+    let mut game = Ale::new().load_rom(&rom);
+    let actions = game.minimal_action_set();
+    let mut next = 0;
+    let mut act = move |game: &mut Game| {
+        next = (next + 1) % actions.len();
+        let reward = game.act(Action(actions[next].0));
+        if game.is_over() {
+            game.reset();
+        }
+        reward
+    };
+    // Start from a state partway into the game, not the title screen.
+    for _ in 0..200 {
+        act(&mut game);
+    }
+    let state = game.clone_state();
+    let system_state = game.clone_system_state();
+    let state_bytes = state.to_bytes();
+    let mut buf = Vec::new();
+    let (width, height) = game.screen_dimensions();
+    let mut raw_buf = vec![0u8; (width * height) as usize];
+
+    let mut b = Bencher { game: &mut game, filter: filter, results: Vec::new() };
+
+    b.bench("act", |game| { black_box(act(game)); });
+    b.bench("step", |game| {
+        black_box(game.step(Action(0)));
+        if game.is_over() {
+            game.reset();
+        }
+    });
+
+    b.bench("raw_get_screen", |game| {
+        unsafe { ale::ffi::getScreen(game.as_raw_ptr(), raw_buf.as_mut_ptr()); }
+        black_box(&raw_buf);
+    });
+    b.bench("screen", |game| { black_box(game.screen()); });
+    b.bench("screen_in_buf", |game| { game.screen_in_buf(&mut buf); black_box(&buf); });
+    b.bench("screen_rgb", |game| { black_box(game.screen_rgb()); });
+    b.bench("screen_rgb_in_buf", |game| { game.screen_rgb_in_buf(&mut buf); black_box(&buf); });
+    b.bench("ram", |game| { black_box(game.ram()); });
+    b.bench("ram_in_buf", |game| { game.ram_in_buf(&mut buf); black_box(&buf); });
+    b.bench("legal_action_set", |game| { black_box(game.legal_action_set()); });
+    b.bench("minimal_action_set", |game| { black_box(game.minimal_action_set()); });
+
+    b.bench("clone_state", |game| { black_box(game.clone_state()); });
+    b.bench("clone_system_state", |game| { black_box(game.clone_system_state()); });
+    b.bench("restore_state", |game| { game.restore_from_cloned_state(&state).unwrap(); });
+    b.bench("restore_system_state", |game| { game.restore_from_cloned_system_state(&system_state).unwrap(); });
+
+    b.bench("state_to_bytes", |_| { black_box(state.to_bytes()); });
+    b.bench("state_from_bytes", |_| { black_box(AleState::from_bytes(&state_bytes).unwrap()); });
+    b.bench("system_state_to_bytes", |_| { black_box(system_state.to_bytes()); });
+    b.bench("state_fingerprint", |_| { black_box(state.fingerprint()); });
+    #[cfg(feature="rustc-serialize")]
+    b.bench("encode_game", |game| { black_box(ale::binary::encode(game).unwrap()); });
+
+    let results = b.results;
+    match write_json(&json, &rom, &results) {
+        Ok(()) => println!("wrote {}", json.display()),
+        Err(err) => println!("failed to write {}: {}", json.display(), err),
+    }
+}
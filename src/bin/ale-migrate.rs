@@ -0,0 +1,89 @@
+//! Converts save games written by `LegacyGameDecoder`-era versions of this
+//! crate to the current format.
+//!
+//! ```text
+//! ale-migrate [--out-dir DIR] [--rom-dir DIR] FILE...
+//! ```
+//!
+//! Files are converted in place unless `--out-dir` is given. Verifying a
+//! conversion restores the game, which needs its ROM on disk: ROMs are
+//! written to the `RomCache` at `--rom-dir` if given, otherwise to a
+//! temporary directory that is removed on exit. Exits with a non-zero
+//! status if any file could not be migrated.
+extern crate ale;
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use ale::RomCache;
+use ale::migrate::{self,Outcome};
+
+const USAGE: &'static str = "usage: ale-migrate [--out-dir DIR] [--rom-dir DIR] FILE...";
+
+fn main() {
+    let mut out_dir = None;
+    let mut rom_dir = None;
+    let mut files = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--out-dir" => match args.next() {
+                Some(dir) => out_dir = Some(PathBuf::from(dir)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                },
+            },
+            "--rom-dir" => match args.next() {
+                Some(dir) => rom_dir = Some(PathBuf::from(dir)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                },
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+
+    if files.is_empty() {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let cache = match rom_dir {
+        Some(dir) => RomCache::new(dir),
+        None => match RomCache::temporary() {
+            Ok(cache) => cache,
+            Err(err) => {
+                eprintln!("could not create a temporary ROM directory: {}", err);
+                process::exit(1);
+            },
+        },
+    };
+
+    let reports = migrate::migrate_files(&files, out_dir.as_ref().map(|dir| dir.as_path()), &cache);
+    // Exiting skips destructors, so remove a temporary cache first.
+    drop(cache);
+
+    let mut failures = 0;
+    for report in &reports {
+        match report.result {
+            Ok(Outcome::Migrated) => println!("{}: migrated", report.path.display()),
+            Ok(Outcome::AlreadyCurrent) => println!("{}: already current", report.path.display()),
+            Err(ref err) => {
+                failures += 1;
+                println!("{}: FAILED: {}", report.path.display(), err);
+            },
+        }
+    }
+
+    if failures > 0 {
+        eprintln!("{} of {} files failed", failures, reports.len());
+        process::exit(1);
+    }
+}
@@ -0,0 +1,387 @@
+//! Plays a ROM from the keyboard in a terminal and records the session, for
+//! collecting human demonstrations.
+//!
+//! ```text
+//! ale-play [--out FILE] [--seed SEED] [--fps N] [--sixel] [--frames] ROM
+//! ```
+//!
+//! The game runs at a fixed rate, 60 steps a second by default, and is drawn
+//! in the terminal. Keys map to the 18 Atari actions:
+//!
+//! ```text
+//! q w e     up-left    up     up-right
+//! a s d     left       noop   right
+//! z x c     down-left  down   down-right
+//! ```
+//!
+//! Arrow keys move too, space fires, and holding shift (or typing the
+//! capital letter) adds fire to a direction. Terminals only report key
+//! presses, not releases, so an action is held until a short while after its
+//! key stops repeating. Ctrl-C or Ctrl-D quits. A finished game is reset and
+//! play continues.
+//!
+//! The session is recorded with `Recorder` to `FILE`, `demo-<time>.alerec`
+//! by default. With `--frames` the screen after every step is stored too,
+//! otherwise frames can be regenerated with a `Replayer`.
+extern crate ale;
+
+use std::env;
+use std::fs::File;
+use std::io::{self,BufWriter,Read,Write};
+use std::process::{self,Command,Stdio};
+use std::thread;
+use std::time::{Duration,Instant,SystemTime,UNIX_EPOCH};
+use ale::{Action,Ale,Recorder};
+use ale::record::RecordOptions;
+use ale::terminal::{RenderMode,TerminalRenderer};
+
+const USAGE: &'static str = "usage: ale-play [--out FILE] [--seed SEED] [--fps N] [--sixel] [--frames] ROM";
+
+/// How long an action is held after its last key press, in steps. Keyboard
+/// repeat usually starts after 250-500 ms and then repeats every 30-50 ms.
+const HOLD_STEPS: u32 = 30;
+const REPEAT_HOLD_STEPS: u32 = 4;
+
+const NOOP: i32 = 0;
+const FIRE: i32 = 1;
+
+/// Puts the terminal in raw mode until dropped.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let saved = try!(stty(&["-g"]));
+        // Reads return immediately, with or without input.
+        try!(stty(&["raw", "-echo", "min", "0", "time", "0"]));
+
+        Ok(RawMode { saved: saved.trim().to_owned() })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let tty = try!(File::open("/dev/tty"));
+    let output = try!(Command::new("stty").args(args).stdin(tty).stderr(Stdio::inherit()).output());
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, "stty failed"));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Action(i32),
+    Quit,
+}
+
+/// The action of an arrow key by the final byte of its escape sequence,
+/// with fire if shift is held.
+fn arrow(code: u8, shift: bool) -> Option<i32> {
+    let action = match code {
+        b'A' => 2,
+        b'C' => 3,
+        b'D' => 4,
+        b'B' => 5,
+        _ => return None,
+    };
+
+    Some(if shift { action + 8 } else { action })
+}
+
+/// Parses the escape sequence at the start of `input`, returning its length
+/// and the key it stands for, if any. Returns `None` if the sequence is
+/// incomplete. Unknown sequences are skipped whole, up to their final byte.
+fn escape_sequence(input: &[u8]) -> Option<(usize, Option<Key>)> {
+    match input.get(1) {
+        None => None,
+        // SS3, e.g. ESC O A for up in application cursor mode.
+        Some(&b'O') => input.get(2).map(|&code| (3, arrow(code, false).map(Key::Action))),
+        // CSI: parameter and intermediate bytes, then a final byte, e.g.
+        // ESC [ A, or ESC [ 1 ; 2 A for shift-up.
+        Some(&b'[') => {
+            let end = 2 + input[2..].iter().take_while(|&&b| b >= 0x20 && b <= 0x3F).count();
+            let code = match input.get(end) {
+                Some(&code) => code,
+                None => return None,
+            };
+            if code < 0x40 || code > 0x7E {
+                // Malformed, drop what was read so far.
+                return Some((end, None));
+            }
+
+            // The modifier is the second parameter, 1 plus a bit mask in
+            // which 1 is shift.
+            let params = String::from_utf8_lossy(&input[2..end]).into_owned();
+            let modifier = params.split(';').nth(1).and_then(|m| m.parse::<u32>().ok()).unwrap_or(1);
+            let shift = modifier > 1 && (modifier - 1) & 1 != 0;
+
+            Some((end + 1, arrow(code, shift).map(Key::Action)))
+        },
+        // A lone escape, or alt with a key, which is read as the key.
+        Some(_) => Some((1, None)),
+    }
+}
+
+fn plain_key(byte: u8) -> Option<Key> {
+    let action = match byte {
+        3 | 4 => return Some(Key::Quit),
+        b' ' | b'S' => FIRE,
+        b's' => NOOP,
+        b'w' => 2,
+        b'd' => 3,
+        b'a' => 4,
+        b'x' => 5,
+        b'e' => 6,
+        b'q' => 7,
+        b'c' => 8,
+        b'z' => 9,
+        b'W' => 10,
+        b'D' => 11,
+        b'A' => 12,
+        b'X' => 13,
+        b'E' => 14,
+        b'Q' => 15,
+        b'C' => 16,
+        b'Z' => 17,
+        _ => return None,
+    };
+
+    Some(Key::Action(action))
+}
+
+/// Maps keys and arrow key escape sequences to actions. A sequence split
+/// across reads is kept until the next one.
+#[derive(Default)]
+struct KeyParser {
+    pending: Vec<u8>,
+}
+
+impl KeyParser {
+    fn parse(&mut self, input: &[u8]) -> Vec<Key> {
+        // Terminals write a sequence at once, so one still incomplete when
+        // nothing more arrived was a lone escape.
+        if input.is_empty() {
+            self.pending.clear();
+        }
+        self.pending.extend_from_slice(input);
+
+        let mut keys = Vec::new();
+        let mut i = 0;
+        while i < self.pending.len() {
+            if self.pending[i] == 0x1b {
+                match escape_sequence(&self.pending[i..]) {
+                    Some((len, key)) => {
+                        keys.extend(key);
+                        i += len;
+                    },
+                    None => break,
+                }
+            } else {
+                keys.extend(plain_key(self.pending[i]));
+                i += 1;
+            }
+        }
+        self.pending.drain(..i);
+
+        keys
+    }
+}
+
+struct Options {
+    rom: String,
+    out: String,
+    seed: Option<u64>,
+    fps: u32,
+    mode: RenderMode,
+    frames: bool,
+}
+
+fn usage_error() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut rom = None;
+    let mut out = None;
+    let mut seed = None;
+    let mut fps = 60;
+    let mut mode = RenderMode::HalfBlocks;
+    let mut frames = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--out" => out = Some(args.next().unwrap_or_else(|| usage_error())),
+            "--seed" => seed = Some(args.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage_error())),
+            "--fps" => fps = args.next().and_then(|s| s.parse().ok()).filter(|&fps| fps > 0).unwrap_or_else(|| usage_error()),
+            "--sixel" => mode = RenderMode::Sixel,
+            "--frames" => frames = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => usage_error(),
+        }
+    }
+
+    let out = out.unwrap_or_else(|| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        format!("demo-{}.alerec", now)
+    });
+
+    Options {
+        rom: rom.unwrap_or_else(|| usage_error()),
+        out: out,
+        seed: seed,
+        fps: fps,
+        mode: mode,
+        frames: frames,
+    }
+}
+
+fn play(options: &Options) -> Result<(u64, i32), ale::Error> {
+    let ale = Ale::new();
+    let game = match options.seed {
+        Some(seed) => ale.load_rom_seeded(&options.rom, seed, 0),
+        None => ale.load_rom(&options.rom),
+    };
+
+    let record_options = RecordOptions { screen: false, screen_rgb: options.frames, ram: false };
+    let out = BufWriter::new(try!(File::create(&options.out)));
+    let mut recorder = try!(Recorder::new(game, out, record_options));
+
+    let mut renderer = TerminalRenderer::new(options.mode);
+    let mut stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut input = [0u8; 64];
+    let mut keys = KeyParser::default();
+
+    let raw = try!(RawMode::enable());
+    let period = Duration::from_nanos(1_000_000_000 / options.fps as u64);
+    let mut deadline = Instant::now();
+    let mut action = NOOP;
+    let mut hold = 0;
+    let mut steps = 0;
+    let mut episode_return = 0;
+    let mut best_return = ::std::i32::MIN;
+
+    'play: loop {
+        let n = try!(stdin.read(&mut input));
+        for key in keys.parse(&input[..n]) {
+            match key {
+                Key::Quit => break 'play,
+                Key::Action(pressed) => {
+                    // A repeat of the held key only needs to bridge the gap
+                    // to the next repeat.
+                    hold = if pressed == action && hold > 0 { ::std::cmp::max(hold, REPEAT_HOLD_STEPS) } else { HOLD_STEPS };
+                    action = pressed;
+                },
+            }
+        }
+        if hold == 0 {
+            action = NOOP;
+        } else {
+            hold -= 1;
+        }
+
+        let info = try!(recorder.step(Action(action)));
+        steps += 1;
+        episode_return += info.reward;
+        if info.terminal {
+            best_return = ::std::cmp::max(best_return, episode_return);
+            episode_return = 0;
+            try!(recorder.reset());
+        }
+
+        try!(renderer.draw(&mut stdout, &recorder, episode_return));
+
+        deadline += period;
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        } else {
+            // Running behind, don't try to catch up.
+            deadline = now;
+        }
+    }
+
+    try!(renderer.restore(&mut stdout));
+    drop(raw);
+    let (_, mut out) = try!(recorder.finish());
+    try!(out.flush());
+
+    Ok((steps, ::std::cmp::max(best_return, episode_return)))
+}
+
+fn main() {
+    let options = parse_args();
+
+    match play(&options) {
+        Ok((steps, best_return)) => {
+            println!("recorded {} steps to {}, best return {}", steps, options.out, best_return);
+        },
+        Err(err) => {
+            let _ = write!(io::stdout(), "\x1b[0m\x1b[?25h");
+            eprintln!("ale-play: {}", err);
+            process::exit(1);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8]) -> Vec<Key> {
+        KeyParser::default().parse(input)
+    }
+
+    #[test]
+    fn letters_and_arrows() {
+        assert_eq!(parse(b"wS \x03"), vec![Key::Action(2), Key::Action(FIRE), Key::Action(FIRE), Key::Quit]);
+        assert_eq!(parse(b"\x1b[A\x1b[B\x1b[C\x1b[D"), vec![Key::Action(2), Key::Action(5), Key::Action(3), Key::Action(4)]);
+        assert_eq!(parse(b"\x1bOA\x1bOD"), vec![Key::Action(2), Key::Action(4)]);
+    }
+
+    #[test]
+    fn modified_arrows() {
+        // Shift adds fire, other modifiers are ignored.
+        assert_eq!(parse(b"\x1b[1;2A\x1b[1;5D\x1b[1;6C"), vec![Key::Action(10), Key::Action(4), Key::Action(11)]);
+    }
+
+    #[test]
+    fn unknown_sequences_are_skipped_whole() {
+        // F5, page up and a mouse report don't leak their bytes as keys.
+        assert_eq!(parse(b"\x1b[15~\x1b[5~\x1b[<0;12;5Mw"), vec![Key::Action(2)]);
+        assert_eq!(parse(b"\x1bOPd"), vec![Key::Action(3)]);
+    }
+
+    #[test]
+    fn sequences_split_across_reads() {
+        let mut parser = KeyParser::default();
+        assert_eq!(parser.parse(b"w\x1b"), vec![Key::Action(2)]);
+        assert_eq!(parser.parse(b"["), vec![]);
+        assert_eq!(parser.parse(b"1;2"), vec![]);
+        assert_eq!(parser.parse(b"Bd"), vec![Key::Action(13), Key::Action(3)]);
+    }
+
+    #[test]
+    fn lone_escapes_are_dropped() {
+        let mut parser = KeyParser::default();
+        assert_eq!(parser.parse(b"\x1b"), vec![]);
+        assert_eq!(parser.parse(b""), vec![]);
+        assert_eq!(parser.parse(b"A"), vec![Key::Action(12)]);
+
+        assert_eq!(parse(b"\x1bx"), vec![Key::Action(5)]);
+    }
+}
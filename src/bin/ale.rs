@@ -0,0 +1,341 @@
+//! Inspects, runs, benchmarks and records ROMs from the command line.
+//!
+//! ```text
+//! ale info ROM
+//! ale run ROM [--policy random|noop] [--episodes N] [--seed SEED] [--render]
+//! ale bench ROM [--steps N] [--seed SEED]
+//! ale record ROM --out DIR [--policy random|noop] [--episodes N] [--seed SEED] [--no-frames]
+//! ```
+//!
+//! `run` prints the score of every episode. `bench` measures steps and
+//! emulated frames per second without fetching the screen and with
+//! `Game::screen` and `Game::screen_rgb`. `record` writes a trajectory to
+//! `DIR/trajectory.alerec`, see `Recorder`, and the RGB screen at the start
+//! of every episode and after every step to
+//! `DIR/episode-<n>/frame-<m>.png`.
+//!
+//! With `--seed` the game is loaded with `Ale::load_rom_seeded` and the
+//! random policy is seeded from it, so runs can be repeated.
+extern crate ale;
+
+use std::env;
+use std::fs::{self,File};
+use std::io::{self,BufWriter,Write};
+use std::path::{Path,PathBuf};
+use std::process;
+use std::time::{Instant,SystemTime,UNIX_EPOCH};
+use ale::{Action,Ale,Error,Game,Recorder};
+use ale::record::RecordOptions;
+use ale::registry;
+use ale::seed::{Rng,stream_seed};
+use ale::terminal::{RenderMode,TerminalRenderer};
+use ale::video::{ColorType,write_png};
+
+const USAGE: &'static str = "usage: ale info ROM
+       ale run ROM [--policy random|noop] [--episodes N] [--seed SEED] [--render]
+       ale bench ROM [--steps N] [--seed SEED]
+       ale record ROM --out DIR [--policy random|noop] [--episodes N] [--seed SEED] [--no-frames]";
+
+/// The names of the 18 Atari actions, by number.
+const ACTION_NAMES: &'static [&'static str] = &[
+    "NOOP", "FIRE", "UP", "RIGHT", "LEFT", "DOWN", "UPRIGHT", "UPLEFT", "DOWNRIGHT", "DOWNLEFT",
+    "UPFIRE", "RIGHTFIRE", "LEFTFIRE", "DOWNFIRE", "UPRIGHTFIRE", "UPLEFTFIRE", "DOWNRIGHTFIRE", "DOWNLEFTFIRE",
+];
+
+/// The random policy draws from its own stream of the seed, past the ones
+/// in `ale::seed`, so it doesn't correlate with them.
+const POLICY_STREAM: u64 = 0x100;
+
+fn usage_error() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Policy {
+    /// Uniformly random actions from the minimal action set.
+    Random,
+    Noop,
+}
+
+struct Actor {
+    policy: Policy,
+    actions: Vec<Action>,
+    rng: Rng,
+}
+
+impl Actor {
+    fn new(policy: Policy, game: &Game, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() ^ d.subsec_nanos() as u64).unwrap_or(0)
+        });
+
+        Actor {
+            policy: policy,
+            actions: game.minimal_action_set(),
+            rng: Rng::new(stream_seed(seed, POLICY_STREAM)),
+        }
+    }
+
+    fn next(&mut self) -> Action {
+        match self.policy {
+            Policy::Random => {
+                let i = self.rng.below(self.actions.len() as u64) as usize;
+                Action(self.actions[i].0)
+            },
+            Policy::Noop => Action(0),
+        }
+    }
+}
+
+/// Options shared by the subcommands. Each one only accepts the flags it
+/// uses.
+struct Options {
+    rom: String,
+    policy: Policy,
+    episodes: u64,
+    steps: u64,
+    seed: Option<u64>,
+    render: bool,
+    out: Option<PathBuf>,
+    frames: bool,
+}
+
+fn parse_options<I: Iterator<Item=String>>(mut args: I, flags: &[&str]) -> Options {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        policy: Policy::Random,
+        episodes: 1,
+        steps: 10000,
+        seed: None,
+        render: false,
+        out: None,
+        frames: true,
+    };
+
+    fn number(value: Option<String>) -> u64 {
+        value.and_then(|v| v.parse().ok()).unwrap_or_else(|| usage_error())
+    }
+
+    while let Some(arg) = args.next() {
+        if arg.starts_with("--") && !flags.contains(&&*arg) {
+            usage_error();
+        }
+
+        match &*arg {
+            "--policy" => options.policy = match args.next().as_ref().map(|p| &**p) {
+                Some("random") => Policy::Random,
+                Some("noop") => Policy::Noop,
+                _ => usage_error(),
+            },
+            "--episodes" => options.episodes = number(args.next()),
+            "--steps" => options.steps = number(args.next()),
+            "--seed" => options.seed = Some(number(args.next())),
+            "--render" => options.render = true,
+            "--out" => options.out = Some(PathBuf::from(args.next().unwrap_or_else(|| usage_error()))),
+            "--no-frames" => options.frames = false,
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => usage_error(),
+        }
+    }
+
+    options.rom = rom.unwrap_or_else(|| usage_error());
+    options
+}
+
+/// Loads the ROM, checking first that it exists since ALE exits the process
+/// on a missing file.
+fn load(options: &Options) -> Result<Game, Error> {
+    if !Path::new(&options.rom).is_file() {
+        return Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, format!("no ROM at {}", options.rom))));
+    }
+
+    let ale = Ale::new();
+    Ok(match options.seed {
+        Some(seed) => ale.load_rom_seeded(&options.rom, seed, 0),
+        None => ale.load_rom(&options.rom),
+    })
+}
+
+fn action_list(actions: &[Action]) -> String {
+    let names: Vec<_> = actions.iter()
+        .map(|a| ACTION_NAMES.get(a.0 as usize).map(|name| name.to_string()).unwrap_or_else(|| a.0.to_string()))
+        .collect();
+
+    format!("{} ({})", names.join(" "), actions.len())
+}
+
+fn info(options: &Options) -> Result<(), Error> {
+    let game = try!(load(options));
+    let md5: Option<String> = game.rom_md5().map(|md5| md5.iter().map(|b| format!("{:02x}", b)).collect());
+    let known = md5.as_ref().and_then(|md5| registry::known_by_md5(md5));
+    let (width, height) = game.screen_dimensions();
+
+    println!("rom             {}", options.rom);
+    println!("md5             {}", md5.as_ref().map(|md5| &**md5).unwrap_or("unknown"));
+    match known {
+        Some(rom) => {
+            let modes: Vec<_> = rom.modes.iter().map(|mode| mode.to_string()).collect();
+            println!("game            {} ({})", rom.name, rom.id);
+            println!("modes           {}", modes.join(" "));
+        },
+        None => {
+            println!("game            not a known ALE ROM");
+            println!("modes           unknown");
+        },
+    }
+    println!("screen          {}x{}", width, height);
+    println!("ram             {} bytes", game.ram_size());
+    println!("legal actions   {}", action_list(&game.legal_action_set()));
+    println!("minimal actions {}", action_list(&game.minimal_action_set()));
+
+    Ok(())
+}
+
+fn run(options: &Options) -> Result<(), Error> {
+    let mut game = try!(load(options));
+    let mut policy = Actor::new(options.policy, &game, options.seed);
+    let mut renderer = TerminalRenderer::new(RenderMode::HalfBlocks);
+    let mut stdout = io::stdout();
+    let mut scores = Vec::with_capacity(options.episodes as usize);
+
+    for episode in 0..options.episodes {
+        let mut score = 0;
+        while !game.is_over() {
+            score += game.act(policy.next());
+            if options.render {
+                try!(renderer.draw(&mut stdout, &game, score));
+            }
+        }
+        if options.render {
+            try!(renderer.restore(&mut stdout));
+        }
+
+        println!("episode {}: score {} in {} frames", episode, score, game.episode_frame_number());
+        scores.push(score as f64);
+        game.reset();
+    }
+
+    if !scores.is_empty() {
+        let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+        let var = scores.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / scores.len() as f64;
+        println!("mean score {:.2} +/- {:.2} over {} episodes", mean, var.sqrt(), scores.len());
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Fetch {
+    Nothing,
+    Screen,
+    ScreenRgb,
+}
+
+fn bench(options: &Options) -> Result<(), Error> {
+    let mut game = try!(load(options));
+    let mut buf = Vec::new();
+
+    for &(fetch, name) in &[(Fetch::Nothing, "no screen"), (Fetch::Screen, "screen"), (Fetch::ScreenRgb, "screen_rgb")] {
+        // The same actions for every run.
+        let mut policy = Actor::new(Policy::Random, &game, Some(options.seed.unwrap_or(0)));
+        game.reset();
+
+        // Resetting a seeded game reloads the ROM, which zeroes
+        // `frame_number`, so count each episode's frames instead.
+        let mut frames = 0u64;
+        let start = Instant::now();
+        for _ in 0..options.steps {
+            game.act(policy.next());
+            match fetch {
+                Fetch::Nothing => {},
+                Fetch::Screen => game.screen_in_buf(&mut buf),
+                Fetch::ScreenRgb => game.screen_rgb_in_buf(&mut buf),
+            }
+            if game.is_over() {
+                frames += game.episode_frame_number() as u64;
+                game.reset();
+            }
+        }
+        frames += game.episode_frame_number() as u64;
+        let elapsed = start.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+        let frames = frames as f64;
+
+        println!("{:<12} {:>10.0} steps/s {:>10.0} frames/s", name, options.steps as f64 / secs, frames / secs);
+    }
+
+    Ok(())
+}
+
+fn record(options: &Options) -> Result<(), Error> {
+    let dir = options.out.clone().unwrap_or_else(|| usage_error());
+    try!(fs::create_dir_all(&dir));
+
+    let game = try!(load(options));
+    let mut policy = Actor::new(options.policy, &game, options.seed);
+    let path = dir.join("trajectory.alerec");
+    let out = BufWriter::new(try!(File::create(&path)));
+    let mut recorder = try!(Recorder::new(game, out, RecordOptions::default()));
+    let (width, height) = recorder.screen_dimensions();
+    let mut buf = Vec::new();
+
+    for episode in 0..options.episodes {
+        let frames = dir.join(format!("episode-{:06}", episode));
+        if options.frames {
+            try!(fs::create_dir_all(&frames));
+        }
+
+        let mut score = 0;
+        let mut step = 0;
+        loop {
+            if options.frames {
+                recorder.screen_rgb_in_buf(&mut buf);
+                let mut file = BufWriter::new(try!(File::create(frames.join(format!("frame-{:06}.png", step)))));
+                try!(write_png(&mut file, width as u32, height as u32, ColorType::Rgb, &buf));
+                try!(file.flush());
+            }
+            if recorder.is_over() {
+                break;
+            }
+
+            score += try!(recorder.step(policy.next())).reward;
+            step += 1;
+        }
+
+        println!("episode {}: score {} in {} steps", episode, score, step);
+        if episode + 1 < options.episodes {
+            try!(recorder.reset());
+        }
+    }
+
+    // Dropping a BufWriter ignores errors writing what's left in it.
+    let (_, mut out) = try!(recorder.finish());
+    try!(out.flush());
+    println!("wrote {}", path.display());
+
+    Ok(())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let command = args.next().unwrap_or_else(|| usage_error());
+
+    let result = match &*command {
+        "info" => info(&parse_options(args, &[])),
+        "run" => run(&parse_options(args, &["--policy", "--episodes", "--seed", "--render"])),
+        "bench" => bench(&parse_options(args, &["--steps", "--seed"])),
+        "record" => record(&parse_options(args, &["--out", "--policy", "--episodes", "--seed", "--no-frames"])),
+        "-h" | "--help" | "help" => {
+            println!("{}", USAGE);
+            return;
+        },
+        _ => usage_error(),
+    };
+
+    if let Err(err) = result {
+        eprintln!("ale {}: {}", command, err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,373 @@
+//! A compact binary encoder and decoder for rustc-serialize.
+//!
+//! Values are laid out back to back with no field names or type tags:
+//!
+//! * integers and floats are big-endian at their natural width, `usize` and
+//!   `isize` as 64 bits
+//! * `bool` is one byte, 0 or 1
+//! * `char` is its UTF-8 encoding
+//! * strings, sequences and maps are a `u64` length followed by their
+//!   bytes, elements, or alternating keys and values
+//! * options are a byte, 0 for `None` or 1 for `Some` followed by the value
+//! * enum variants are their index as a `u32` followed by their fields
+//! * structs and tuples are their fields in order
+use std::io::{Read,Write};
+use rustc_serialize::{self,Encodable,Decodable};
+use ::Error;
+
+/// Sequences and maps longer than this are rejected when decoding, so a
+/// corrupt length can't trigger a huge allocation.
+const MAX_LEN: usize = 1 << 26;
+/// Strings are read incrementally past this size for the same reason.
+const MAX_PREALLOCATION: usize = 1 << 20;
+
+/// Encodes a value to a new buffer.
+pub fn encode<T: Encodable>(value: &T) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    try!(value.encode(&mut Encoder::new(&mut buf)));
+
+    Ok(buf)
+}
+
+/// Decodes a value from the start of a buffer.
+pub fn decode<T: Decodable>(bytes: &[u8]) -> Result<T, Error> {
+    let mut reader = bytes;
+    T::decode(&mut Decoder::new(&mut reader))
+}
+
+pub struct Encoder<'a, W: Write + 'a> {
+    w: &'a mut W,
+}
+
+impl<'a, W: Write> Encoder<'a, W> {
+    pub fn new(w: &'a mut W) -> Self {
+        Encoder { w: w }
+    }
+
+    fn write_be(&mut self, v: u64, bytes: usize) -> Result<(), Error> {
+        let mut buf = [0u8; 8];
+        for i in 0..bytes {
+            buf[i] = (v >> (8 * (bytes - 1 - i))) as u8;
+        }
+
+        self.w.write_all(&buf[..bytes]).map_err(Error::from)
+    }
+}
+
+impl<'a, W: Write> rustc_serialize::Encoder for Encoder<'a, W> {
+    type Error = Error;
+
+    fn emit_nil(&mut self) -> Result<(), Error> { Ok(()) }
+    fn emit_usize(&mut self, v: usize) -> Result<(), Error> { self.write_be(v as u64, 8) }
+    fn emit_u64(&mut self, v: u64) -> Result<(), Error> { self.write_be(v, 8) }
+    fn emit_u32(&mut self, v: u32) -> Result<(), Error> { self.write_be(v as u64, 4) }
+    fn emit_u16(&mut self, v: u16) -> Result<(), Error> { self.write_be(v as u64, 2) }
+    fn emit_u8(&mut self, v: u8) -> Result<(), Error> { self.write_be(v as u64, 1) }
+    fn emit_isize(&mut self, v: isize) -> Result<(), Error> { self.write_be(v as i64 as u64, 8) }
+    fn emit_i64(&mut self, v: i64) -> Result<(), Error> { self.write_be(v as u64, 8) }
+    fn emit_i32(&mut self, v: i32) -> Result<(), Error> { self.write_be(v as u32 as u64, 4) }
+    fn emit_i16(&mut self, v: i16) -> Result<(), Error> { self.write_be(v as u16 as u64, 2) }
+    fn emit_i8(&mut self, v: i8) -> Result<(), Error> { self.write_be(v as u8 as u64, 1) }
+    fn emit_bool(&mut self, v: bool) -> Result<(), Error> { self.write_be(v as u64, 1) }
+    fn emit_f64(&mut self, v: f64) -> Result<(), Error> { self.write_be(v.to_bits(), 8) }
+    fn emit_f32(&mut self, v: f32) -> Result<(), Error> { self.write_be(v.to_bits() as u64, 4) }
+
+    fn emit_char(&mut self, v: char) -> Result<(), Error> {
+        let mut buf = [0u8; 4];
+        let encoded = v.encode_utf8(&mut buf);
+        self.w.write_all(encoded.as_bytes()).map_err(Error::from)
+    }
+
+    fn emit_str(&mut self, v: &str) -> Result<(), Error> {
+        try!(self.emit_usize(v.len()));
+        self.w.write_all(v.as_bytes()).map_err(Error::from)
+    }
+
+    fn emit_enum<F>(&mut self, _: &str, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_enum_variant<F>(&mut self, _: &str, v_id: usize, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        try!(self.emit_u32(v_id as u32));
+        f(self)
+    }
+
+    fn emit_enum_variant_arg<F>(&mut self, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_enum_struct_variant<F>(&mut self, v_name: &str, v_id: usize, len: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        self.emit_enum_variant(v_name, v_id, len, f)
+    }
+
+    fn emit_enum_struct_variant_field<F>(&mut self, _: &str, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_struct<F>(&mut self, _: &str, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_struct_field<F>(&mut self, _: &str, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_tuple<F>(&mut self, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_tuple_arg<F>(&mut self, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_tuple_struct<F>(&mut self, _: &str, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_tuple_struct_arg<F>(&mut self, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_option<F>(&mut self, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_option_none(&mut self) -> Result<(), Error> {
+        self.emit_u8(0)
+    }
+
+    fn emit_option_some<F>(&mut self, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        try!(self.emit_u8(1));
+        f(self)
+    }
+
+    fn emit_seq<F>(&mut self, len: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        try!(self.emit_usize(len));
+        f(self)
+    }
+
+    fn emit_seq_elt<F>(&mut self, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_map<F>(&mut self, len: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        try!(self.emit_usize(len));
+        f(self)
+    }
+
+    fn emit_map_elt_key<F>(&mut self, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+
+    fn emit_map_elt_val<F>(&mut self, _: usize, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+        f(self)
+    }
+}
+
+pub struct Decoder<'a, R: Read + 'a> {
+    r: &'a mut R,
+}
+
+impl<'a, R: Read> Decoder<'a, R> {
+    pub fn new(r: &'a mut R) -> Self {
+        Decoder { r: r }
+    }
+
+    fn read_be(&mut self, bytes: usize) -> Result<u64, Error> {
+        let mut buf = [0u8; 8];
+        try!(self.r.read_exact(&mut buf[..bytes]));
+
+        Ok(buf[..bytes].iter().fold(0, |v, &b| (v << 8) | b as u64))
+    }
+
+    fn read_len(&mut self) -> Result<usize, Error> {
+        let len = try!(self.read_be(8));
+        if len > usize::max_value() as u64 {
+            return Err(Error::Format("length does not fit in memory".to_owned()));
+        }
+
+        Ok(len as usize)
+    }
+}
+
+impl<'a, R: Read> rustc_serialize::Decoder for Decoder<'a, R> {
+    type Error = Error;
+
+    fn read_nil(&mut self) -> Result<(), Error> { Ok(()) }
+    fn read_usize(&mut self) -> Result<usize, Error> { self.read_len() }
+    fn read_u64(&mut self) -> Result<u64, Error> { self.read_be(8) }
+    fn read_u32(&mut self) -> Result<u32, Error> { self.read_be(4).map(|v| v as u32) }
+    fn read_u16(&mut self) -> Result<u16, Error> { self.read_be(2).map(|v| v as u16) }
+    fn read_u8(&mut self) -> Result<u8, Error> { self.read_be(1).map(|v| v as u8) }
+    fn read_isize(&mut self) -> Result<isize, Error> { self.read_be(8).map(|v| v as i64 as isize) }
+    fn read_i64(&mut self) -> Result<i64, Error> { self.read_be(8).map(|v| v as i64) }
+    fn read_i32(&mut self) -> Result<i32, Error> { self.read_be(4).map(|v| v as u32 as i32) }
+    fn read_i16(&mut self) -> Result<i16, Error> { self.read_be(2).map(|v| v as u16 as i16) }
+    fn read_i8(&mut self) -> Result<i8, Error> { self.read_be(1).map(|v| v as u8 as i8) }
+    fn read_f64(&mut self) -> Result<f64, Error> { self.read_be(8).map(f64::from_bits) }
+    fn read_f32(&mut self) -> Result<f32, Error> { self.read_be(4).map(|v| f32::from_bits(v as u32)) }
+
+    fn read_bool(&mut self) -> Result<bool, Error> {
+        match try!(self.read_u8()) {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Format("invalid bool".to_owned())),
+        }
+    }
+
+    fn read_char(&mut self) -> Result<char, Error> {
+        let mut buf = [0u8; 4];
+        try!(self.r.read_exact(&mut buf[..1]));
+
+        let width = match buf[0] {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Err(Error::Format("invalid char".to_owned())),
+        };
+        try!(self.r.read_exact(&mut buf[1..width]));
+
+        match ::std::str::from_utf8(&buf[..width]) {
+            Ok(s) => Ok(s.chars().next().unwrap()),
+            Err(_) => Err(Error::Format("invalid char".to_owned())),
+        }
+    }
+
+    fn read_str(&mut self) -> Result<String, Error> {
+        let len = try!(self.read_len());
+        let mut buf = Vec::with_capacity(::std::cmp::min(len, MAX_PREALLOCATION));
+        try!(self.r.by_ref().take(len as u64).read_to_end(&mut buf));
+        if buf.len() != len {
+            return Err(Error::Format("unexpected end of string".to_owned()));
+        }
+
+        String::from_utf8(buf).map_err(|_| Error::Format("invalid UTF-8 in string".to_owned()))
+    }
+
+    fn read_enum<T, F>(&mut self, _: &str, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_enum_variant<T, F>(&mut self, names: &[&str], mut f: F) -> Result<T, Error>
+        where F: FnMut(&mut Self, usize) -> Result<T, Error> {
+        let id = try!(self.read_u32()) as usize;
+        if id >= names.len() {
+            return Err(Error::Format("invalid enum variant".to_owned()));
+        }
+
+        f(self, id)
+    }
+
+    fn read_enum_variant_arg<T, F>(&mut self, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_enum_struct_variant<T, F>(&mut self, names: &[&str], f: F) -> Result<T, Error>
+        where F: FnMut(&mut Self, usize) -> Result<T, Error> {
+        self.read_enum_variant(names, f)
+    }
+
+    fn read_enum_struct_variant_field<T, F>(&mut self, _: &str, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_struct<T, F>(&mut self, _: &str, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_struct_field<T, F>(&mut self, _: &str, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_tuple<T, F>(&mut self, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_tuple_arg<T, F>(&mut self, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_tuple_struct<T, F>(&mut self, _: &str, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_tuple_struct_arg<T, F>(&mut self, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_option<T, F>(&mut self, mut f: F) -> Result<T, Error>
+        where F: FnMut(&mut Self, bool) -> Result<T, Error> {
+        match try!(self.read_u8()) {
+            0 => f(self, false),
+            1 => f(self, true),
+            _ => Err(Error::Format("invalid option tag".to_owned())),
+        }
+    }
+
+    fn read_seq<T, F>(&mut self, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self, usize) -> Result<T, Error> {
+        let len = try!(self.read_len());
+        if len > MAX_LEN {
+            return Err(Error::Format("sequence is too long".to_owned()));
+        }
+
+        f(self, len)
+    }
+
+    fn read_seq_elt<T, F>(&mut self, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_map<T, F>(&mut self, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self, usize) -> Result<T, Error> {
+        let len = try!(self.read_len());
+        if len > MAX_LEN {
+            return Err(Error::Format("map is too long".to_owned()));
+        }
+
+        f(self, len)
+    }
+
+    fn read_map_elt_key<T, F>(&mut self, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn read_map_elt_val<T, F>(&mut self, _: usize, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error> {
+        f(self)
+    }
+
+    fn error(&mut self, err: &str) -> Error {
+        Error::Format(err.to_owned())
+    }
+}
@@ -0,0 +1,258 @@
+//! Little-endian helpers for the crate's binary file formats.
+use std::fs::File;
+use std::io::{self,Read,Write};
+use std::path::Path;
+use ::Error;
+use ::seed::EpisodeSeed;
+
+pub fn write_u8<W: Write>(w: &mut W, v: u8) -> io::Result<()> {
+    w.write_all(&[v])
+}
+
+pub fn write_u16<W: Write>(w: &mut W, v: u16) -> io::Result<()> {
+    w.write_all(&[v as u8, (v >> 8) as u8])
+}
+
+pub fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    w.write_all(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8])
+}
+
+pub fn write_u64<W: Write>(w: &mut W, v: u64) -> io::Result<()> {
+    try!(write_u32(w, v as u32));
+    write_u32(w, (v >> 32) as u32)
+}
+
+pub fn write_i32<W: Write>(w: &mut W, v: i32) -> io::Result<()> {
+    write_u32(w, v as u32)
+}
+
+pub fn write_f32<W: Write>(w: &mut W, v: f32) -> io::Result<()> {
+    write_u32(w, v.to_bits())
+}
+
+pub fn write_f64<W: Write>(w: &mut W, v: f64) -> io::Result<()> {
+    write_u64(w, v.to_bits())
+}
+
+pub fn write_bool<W: Write>(w: &mut W, v: bool) -> io::Result<()> {
+    write_u8(w, v as u8)
+}
+
+/// Writes a `u64` length followed by the bytes.
+pub fn write_bytes<W: Write>(w: &mut W, v: &[u8]) -> io::Result<()> {
+    try!(write_u64(w, v.len() as u64));
+    w.write_all(v)
+}
+
+pub fn write_str<W: Write>(w: &mut W, v: &str) -> io::Result<()> {
+    write_bytes(w, v.as_bytes())
+}
+
+pub fn read_u8<R: Read>(r: &mut R) -> Result<u8, Error> {
+    let mut buf = [0u8; 1];
+    try!(r.read_exact(&mut buf));
+
+    Ok(buf[0])
+}
+
+pub fn read_u32<R: Read>(r: &mut R) -> Result<u32, Error> {
+    let mut buf = [0u8; 4];
+    try!(r.read_exact(&mut buf));
+
+    Ok(buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24)
+}
+
+pub fn read_u64<R: Read>(r: &mut R) -> Result<u64, Error> {
+    let low = try!(read_u32(r)) as u64;
+    let high = try!(read_u32(r)) as u64;
+
+    Ok(low | high << 32)
+}
+
+pub fn read_i32<R: Read>(r: &mut R) -> Result<i32, Error> {
+    read_u32(r).map(|v| v as i32)
+}
+
+pub fn read_f32<R: Read>(r: &mut R) -> Result<f32, Error> {
+    read_u32(r).map(f32::from_bits)
+}
+
+pub fn read_f64<R: Read>(r: &mut R) -> Result<f64, Error> {
+    read_u64(r).map(f64::from_bits)
+}
+
+pub fn read_bool<R: Read>(r: &mut R) -> Result<bool, Error> {
+    match try!(read_u8(r)) {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(Error::Format("invalid bool".to_owned())),
+    }
+}
+
+/// Reads bytes written by `write_bytes`, refusing lengths over `max`.
+pub fn read_bytes<R: Read>(r: &mut R, max: usize) -> Result<Vec<u8>, Error> {
+    let len = try!(read_u64(r));
+    if len > max as u64 {
+        return Err(Error::Format(format!("length {} exceeds the limit of {}", len, max)));
+    }
+
+    // Read as the data arrives rather than trusting the length, so a
+    // corrupt one fails at the end of the input instead of allocating.
+    let mut buf = Vec::new();
+    try!(r.take(len).read_to_end(&mut buf));
+    if (buf.len() as u64) < len {
+        return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "input ended inside a field")));
+    }
+
+    Ok(buf)
+}
+
+pub fn read_str<R: Read>(r: &mut R, max: usize) -> Result<String, Error> {
+    let bytes = try!(read_bytes(r, max));
+    String::from_utf8(bytes).map_err(|_| Error::Format("invalid UTF-8 in string".to_owned()))
+}
+
+/// Reads a magic number and fails if it doesn't match.
+pub fn expect_magic<R: Read>(r: &mut R, magic: &[u8], what: &str) -> Result<(), Error> {
+    let mut buf = vec![0u8; magic.len()];
+    try!(r.read_exact(&mut buf));
+    if buf != magic {
+        return Err(Error::Format(format!("not {}", what)));
+    }
+
+    Ok(())
+}
+
+/// Writes an optional episode seed as a flag followed by its fields.
+pub fn write_seed<W: Write>(w: &mut W, seed: Option<EpisodeSeed>) -> io::Result<()> {
+    match seed {
+        Some(seed) => {
+            try!(write_u8(w, 1));
+            try!(write_u64(w, seed.master));
+            try!(write_u64(w, seed.env_id));
+            try!(write_u64(w, seed.episode));
+            write_u64(w, seed.seed)
+        },
+        None => write_u8(w, 0),
+    }
+}
+
+pub fn read_seed<R: Read>(r: &mut R) -> Result<Option<EpisodeSeed>, Error> {
+    if !try!(read_bool(r)) {
+        return Ok(None);
+    }
+
+    Ok(Some(EpisodeSeed {
+        master: try!(read_u64(r)),
+        env_id: try!(read_u64(r)),
+        episode: try!(read_u64(r)),
+        seed: try!(read_u64(r)),
+    }))
+}
+
+/// The MD5 digest of a file's contents. Files longer than `max_len` are
+/// rejected without being read.
+pub fn file_digest(path: &Path, max_len: u64) -> Result<[u8; 16], Error> {
+    let file = try!(File::open(path));
+    if try!(file.metadata()).len() > max_len {
+        return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, "file is too large")));
+    }
+
+    let mut data = Vec::new();
+    try!(file.take(max_len).read_to_end(&mut data));
+
+    Ok(::md5::compute(&data).0)
+}
+
+/// Like `file_digest`, formatted as lowercase hex.
+pub fn file_md5(path: &Path, max_len: u64) -> Result<String, Error> {
+    let digest = try!(file_digest(path, max_len));
+
+    Ok(format!("{:x}", ::md5::Digest(digest)))
+}
+
+/// A 64-bit FNV-1a hash, used for cheap content digests.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+/// The CRC-32 used by zip and PNG.
+pub struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+
+        Crc32 { table: table, crc: 0xFFFFFFFF }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc = self.table[((self.crc ^ byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.crc ^ 0xFFFFFFFF
+    }
+
+    /// Starts over without rebuilding the table.
+    pub fn reset(&mut self) {
+        self.crc = 0xFFFFFFFF;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self,File};
+    use std::io::Write;
+    use std::process;
+    use super::*;
+
+    #[test]
+    fn file_md5_hashes_the_contents() {
+        let path = env::temp_dir().join(format!("ale-bytes-test-{}", process::id()));
+        write_file(&path, b"abc");
+
+        assert_eq!(file_md5(&path, 3).unwrap(), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(file_digest(&path, 3).unwrap()[..2], [0x90, 0x01]);
+        assert!(file_md5(&path, 2).is_err());
+
+        fs::remove_file(&path).unwrap();
+        assert!(file_md5(&path, 3).is_err());
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF43926);
+
+        crc.reset();
+        assert_eq!(crc.finish(), 0);
+        crc.update(b"The quick brown fox jumps over the lazy dog");
+        assert_eq!(crc.finish(), 0x414FA339);
+    }
+
+    fn write_file(path: &Path, data: &[u8]) {
+        File::create(path).unwrap().write_all(data).unwrap();
+    }
+}
@@ -0,0 +1,616 @@
+//! Compact replays that store actions instead of observations.
+//!
+//! ALE is deterministic given its settings, its starting state and the
+//! actions taken, so a `CompactReplay` keeps only those: the settings, the
+//! starting `AleSystemState` (or just the episode seed, for a game that was
+//! freshly loaded with one), one byte per action and the steps at which the
+//! game was reset. Every `checkpoint_interval` steps it also embeds the state
+//! of the game, so a `Regenerator` can reach any step by replaying at most
+//! that many actions.
+//!
+//! The binary format, written by `CompactReplay::write_to`, is:
+//!
+//! ```text
+//! b"ALE-COMPACT", format version (u8, currently 1),
+//! ROM MD5 (u8 flag, then 16 bytes if the flag is 1), ROM path, Settings,
+//! EpisodeSeed at the start (u8 flag, then master, env id, episode and seed
+//! as u64s), starting AleSystemState (u8 flag, then the state),
+//! checkpoint interval (u64), actions (u64 length, one byte per action),
+//! resets (u64 count, then u64 steps),
+//! checkpoints (u64 count, then for each the step (u64), the EpisodeSeed
+//! and the AleSystemState)
+//! ```
+use std::io::{self,Read,Write};
+use std::ops::{Deref,Range};
+use ::{Action,Ale,AleSystemState,Error,Game,Settings,StepInfo};
+use ::bytes::*;
+use ::game::protected::Protected;
+use ::seed::{self,EpisodeSeed};
+
+const MAGIC: &'static [u8] = b"ALE-COMPACT";
+const FORMAT_VERSION: u8 = 1;
+
+/// Upper bound on the number of actions when reading, about a year of play
+/// at 60 frames per second. Actions are read as they arrive, so a corrupt
+/// length fails at the end of the input rather than allocating this much.
+const MAX_ACTIONS: usize = 1 << 31;
+
+/// The state of a game before a given step, after any reset at that step.
+pub struct Checkpoint {
+    pub step: u64,
+    pub seed: Option<EpisodeSeed>,
+    pub state: AleSystemState,
+}
+
+pub struct CompactReplay {
+    pub rom_md5: Option<[u8; 16]>,
+    pub rom_path: String,
+    pub settings: Settings,
+    /// The seed of the first episode, if the game was seeded.
+    pub seed: Option<EpisodeSeed>,
+    /// The state before the first step, or `None` if the game starts from
+    /// its ROM freshly loaded with `seed`.
+    pub initial_state: Option<AleSystemState>,
+    checkpoint_interval: u64,
+    actions: Vec<u8>,
+    resets: Vec<u64>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl CompactReplay {
+    /// The number of steps in the replay.
+    pub fn len(&self) -> u64 {
+        self.actions.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    pub fn action(&self, step: u64) -> Action {
+        Action(self.actions[step as usize] as i32)
+    }
+
+    /// The steps before which the game was reset, in order. A step appears
+    /// once for every reset before it.
+    pub fn resets(&self) -> &[u64] {
+        &self.resets
+    }
+
+    /// The number of episodes, including an unfinished last one.
+    pub fn episodes(&self) -> u64 {
+        self.resets.len() as u64 + 1
+    }
+
+    pub fn checkpoint_interval(&self) -> u64 {
+        self.checkpoint_interval
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Removes the embedded checkpoints, making the replay as small as
+    /// possible at the cost of slower seeking.
+    pub fn strip_checkpoints(&mut self) {
+        self.checkpoints.clear();
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        try!(w.write_all(MAGIC));
+        try!(write_u8(w, FORMAT_VERSION));
+        match self.rom_md5 {
+            Some(md5) => {
+                try!(write_u8(w, 1));
+                try!(w.write_all(&md5));
+            },
+            None => try!(write_u8(w, 0)),
+        }
+        try!(write_str(w, &self.rom_path));
+        try!(self.settings.write_to(w));
+        try!(write_seed(w, self.seed));
+        match self.initial_state {
+            Some(ref state) => {
+                try!(write_u8(w, 1));
+                try!(state.write_to(w));
+            },
+            None => try!(write_u8(w, 0)),
+        }
+
+        try!(write_u64(w, self.checkpoint_interval));
+        try!(write_bytes(w, &self.actions));
+        try!(write_u64(w, self.resets.len() as u64));
+        for &step in &self.resets {
+            try!(write_u64(w, step));
+        }
+        try!(write_u64(w, self.checkpoints.len() as u64));
+        for checkpoint in &self.checkpoints {
+            try!(write_u64(w, checkpoint.step));
+            try!(write_seed(w, checkpoint.seed));
+            try!(checkpoint.state.write_to(w));
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        try!(expect_magic(r, MAGIC, "an ALE compact replay"));
+        let version = try!(read_u8(r));
+        if version != FORMAT_VERSION {
+            return Err(Error::Format(format!("unsupported compact replay format version {}", version)));
+        }
+
+        let rom_md5 = match try!(read_u8(r)) {
+            0 => None,
+            _ => {
+                let mut md5 = [0u8; 16];
+                try!(r.read_exact(&mut md5));
+                Some(md5)
+            },
+        };
+        let rom_path = try!(read_str(r, 4096));
+        let settings = try!(Settings::read_from(r));
+        let seed = try!(read_seed(r));
+        let initial_state = match try!(read_u8(r)) {
+            0 => None,
+            _ => Some(try!(AleSystemState::read_from(r))),
+        };
+        if initial_state.is_none() && seed.is_none() {
+            return Err(Error::Format("compact replay has neither a starting state nor a seed".to_owned()));
+        }
+
+        let checkpoint_interval = try!(read_u64(r));
+        let actions = try!(read_bytes(r, MAX_ACTIONS));
+
+        let mut resets = Vec::new();
+        for _ in 0..try!(read_u64(r)) {
+            let step = try!(read_u64(r));
+            if step > actions.len() as u64 || resets.last().map_or(false, |&last| last > step) {
+                return Err(Error::Format("compact replay resets are out of order".to_owned()));
+            }
+            resets.push(step);
+        }
+
+        // The recorder embeds a checkpoint every `checkpoint_interval`
+        // steps, after the first step, so there can't be more than that.
+        let checkpoint_count = try!(read_u64(r));
+        let max_checkpoints = match checkpoint_interval {
+            0 => 0,
+            interval => actions.len() as u64 / interval,
+        };
+        if checkpoint_count > max_checkpoints {
+            return Err(Error::Format(format!("compact replay has {} checkpoints, at most {} fit its {} steps",
+                                             checkpoint_count, max_checkpoints, actions.len())));
+        }
+
+        let mut checkpoints: Vec<Checkpoint> = Vec::new();
+        for _ in 0..checkpoint_count {
+            let checkpoint = Checkpoint {
+                step: try!(read_u64(r)),
+                seed: try!(read_seed(r)),
+                state: try!(AleSystemState::read_from(r)),
+            };
+            if checkpoint.step > actions.len() as u64 || checkpoints.last().map_or(false, |last| last.step >= checkpoint.step) {
+                return Err(Error::Format("compact replay checkpoints are out of order".to_owned()));
+            }
+            if checkpoint.step == 0 || checkpoint.step % checkpoint_interval != 0 {
+                return Err(Error::Format("compact replay checkpoint isn't on the checkpoint interval".to_owned()));
+            }
+            checkpoints.push(checkpoint);
+        }
+
+        Ok(CompactReplay {
+            rom_md5: rom_md5,
+            rom_path: rom_path,
+            settings: settings,
+            seed: seed,
+            initial_state: initial_state,
+            checkpoint_interval: checkpoint_interval,
+            actions: actions,
+            resets: resets,
+            checkpoints: checkpoints,
+        })
+    }
+}
+
+/// The number of resets before the given step.
+fn resets_at(resets: &[u64], step: u64) -> usize {
+    let (mut lo, mut hi) = (0, resets.len());
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if resets[mid] < step {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    resets[lo..].iter().take_while(|&&reset| reset == step).count()
+}
+
+/// Wraps a game and records its actions and resets into a `CompactReplay`.
+pub struct CompactRecorder {
+    game: Game,
+    replay: CompactReplay,
+}
+
+impl CompactRecorder {
+    /// Starts recording from the game's current state, embedding a
+    /// checkpoint every `checkpoint_interval` steps, or none if it's 0.
+    pub fn new(game: Game, checkpoint_interval: u64) -> Self {
+        let state = game.clone_system_state();
+        CompactRecorder::start(game, Some(state), checkpoint_interval)
+    }
+
+    /// Starts recording without storing the starting state. The game must
+    /// have just been loaded with `Ale::load_rom_seeded` or
+    /// `Ale::load_rom_with_seed`, since it will be regenerated by loading
+    /// the ROM with the same seed again. Panics if the game isn't seeded.
+    pub fn from_seed(game: Game, checkpoint_interval: u64) -> Self {
+        assert!(game.seed().is_some(), "CompactRecorder::from_seed needs a seeded game");
+        CompactRecorder::start(game, None, checkpoint_interval)
+    }
+
+    fn start(game: Game, initial_state: Option<AleSystemState>, checkpoint_interval: u64) -> Self {
+        let replay = CompactReplay {
+            rom_md5: game.rom_md5(),
+            rom_path: game.rom_path().to_owned(),
+            settings: Settings::capture(&game),
+            seed: game.seed(),
+            initial_state: initial_state,
+            checkpoint_interval: checkpoint_interval,
+            actions: Vec::new(),
+            resets: Vec::new(),
+            checkpoints: Vec::new(),
+        };
+
+        CompactRecorder {
+            game: game,
+            replay: replay,
+        }
+    }
+
+    /// Performs and records an action. Fails without acting if the action
+    /// doesn't fit in a byte, which no ALE action does.
+    pub fn step(&mut self, action: Action) -> Result<StepInfo, Error> {
+        if action.0 < 0 || action.0 > u8::max_value() as i32 {
+            return Err(Error::Format(format!("action {} can't be stored in a compact replay", action.0)));
+        }
+
+        let step = self.replay.len();
+        let interval = self.replay.checkpoint_interval;
+        if interval > 0 && step > 0 && step % interval == 0 {
+            self.replay.checkpoints.push(Checkpoint {
+                step: step,
+                seed: self.game.seed(),
+                state: self.game.clone_system_state(),
+            });
+        }
+
+        let info = self.game.step(action);
+        self.replay.actions.push(action.0 as u8);
+
+        Ok(info)
+    }
+
+    /// Resets the game. A reset before the first step moves the start of
+    /// the replay instead of being recorded.
+    pub fn reset(&mut self) {
+        self.game.reset();
+
+        let step = self.replay.len();
+        if step == 0 {
+            self.replay.seed = self.game.seed();
+            self.replay.initial_state = Some(self.game.clone_system_state());
+        } else {
+            self.replay.resets.push(step);
+        }
+    }
+
+    pub fn replay(&self) -> &CompactReplay {
+        &self.replay
+    }
+
+    pub fn finish(self) -> (Game, CompactReplay) {
+        (self.game, self.replay)
+    }
+}
+
+impl Deref for CompactRecorder {
+    type Target=Game;
+
+    fn deref(&self) -> &Game {
+        &self.game
+    }
+}
+
+/// Regenerates what happened at any step of a `CompactReplay` by replaying
+/// its actions from the nearest checkpoint.
+pub struct Regenerator<'a> {
+    game: Game,
+    replay: &'a CompactReplay,
+    position: u64,
+}
+
+impl<'a> Regenerator<'a> {
+    /// Loads the replay's ROM into a fresh `Ale`, from the path it was
+    /// recorded with.
+    pub fn new(ale: Ale, replay: &'a CompactReplay) -> Result<Self, Error> {
+        Regenerator::with_rom(ale, replay, &replay.rom_path)
+    }
+
+    /// Loads the replay's ROM into a fresh `Ale` from `rom_path`. The ROM
+    /// must be the one that was recorded. Only the recorded settings that
+    /// affect determinism are applied.
+    pub fn with_rom(mut ale: Ale, replay: &'a CompactReplay, rom_path: &str) -> Result<Self, Error> {
+        replay.settings.apply_deterministic(&mut ale);
+        let game = match replay.seed {
+            Some(seed) => ale.load_rom_with_seed(rom_path, seed),
+            None => ale.load_rom(rom_path),
+        };
+
+        if let (Some(recorded), Some(loaded)) = (replay.rom_md5, game.rom_md5()) {
+            if recorded != loaded {
+                return Err(Error::RomMismatch { state: recorded, game: loaded });
+            }
+        }
+
+        let mut regenerator = Regenerator {
+            game: game,
+            replay: replay,
+            position: 0,
+        };
+        if let Some(ref state) = replay.initial_state {
+            try!(regenerator.game.restore_from_cloned_system_state(state));
+        }
+
+        Ok(regenerator)
+    }
+
+    /// The step the game is about to take.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The game, in the state before the step at `position`.
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    fn restart(&mut self) -> Result<(), Error> {
+        match self.replay.initial_state {
+            Some(ref state) => try!(self.game.restore_from_cloned_system_state(state)),
+            None => {
+                let seed = self.replay.seed.expect("compact replay without a starting state or seed");
+                self.game.set_int("random_seed", seed::ale_seed(seed.seed));
+                let rom_path = self.game.rom_path().to_owned();
+                self.game.change_game_in_place(&rom_path);
+            },
+        }
+        self.game.set_seed(self.replay.seed);
+        self.position = 0;
+
+        Ok(())
+    }
+
+    /// Moves to the state before the given step. Panics if the step is past
+    /// the end of the replay.
+    pub fn seek(&mut self, step: u64) -> Result<(), Error> {
+        assert!(step <= self.replay.len(), "Regenerator::seek past the end of the replay");
+
+        let checkpoints = &self.replay.checkpoints;
+        let nearest = match checkpoints.binary_search_by_key(&step, |checkpoint| checkpoint.step) {
+            Ok(i) => Some(i),
+            Err(0) => None,
+            Err(i) => Some(i - 1),
+        };
+        let nearest_step = nearest.map_or(0, |i| checkpoints[i].step);
+
+        if self.position > step || self.position < nearest_step {
+            match nearest {
+                Some(i) => {
+                    let checkpoint = &checkpoints[i];
+                    try!(self.game.restore_from_cloned_system_state(&checkpoint.state));
+                    self.game.set_seed(checkpoint.seed);
+                    self.position = checkpoint.step;
+                },
+                None => try!(self.restart()),
+            }
+        }
+
+        while self.position < step {
+            self.advance(&mut |_, _, _| {});
+        }
+
+        Ok(())
+    }
+
+    fn advance<F: FnMut(u64, &StepInfo, &Game)>(&mut self, f: &mut F) {
+        let info = self.game.step(self.replay.action(self.position));
+        f(self.position, &info, &self.game);
+
+        self.position += 1;
+        for _ in 0..resets_at(&self.replay.resets, self.position) {
+            self.game.reset();
+        }
+    }
+
+    /// Replays the given steps, calling `f` with each step, what happened
+    /// and the game right after it.
+    pub fn for_each<F: FnMut(u64, &StepInfo, &Game)>(&mut self, steps: Range<u64>, mut f: F) -> Result<(), Error> {
+        assert!(steps.end <= self.replay.len(), "Regenerator range past the end of the replay");
+
+        try!(self.seek(steps.start));
+        while self.position < steps.end {
+            self.advance(&mut f);
+        }
+
+        Ok(())
+    }
+
+    /// What happened at each of the given steps.
+    pub fn step_infos(&mut self, steps: Range<u64>) -> Result<Vec<StepInfo>, Error> {
+        let mut infos = Vec::new();
+        try!(self.for_each(steps, |_, info, _| infos.push(*info)));
+
+        Ok(infos)
+    }
+
+    pub fn rewards(&mut self, steps: Range<u64>) -> Result<Vec<i32>, Error> {
+        let mut rewards = Vec::new();
+        try!(self.for_each(steps, |_, info, _| rewards.push(info.reward)));
+
+        Ok(rewards)
+    }
+
+    /// The screens after each of the given steps, as palette indices.
+    pub fn screens(&mut self, steps: Range<u64>) -> Result<Vec<Vec<u8>>, Error> {
+        self.collect(steps, |game, buf| game.screen_in_buf(buf))
+    }
+
+    /// The RGB screens after each of the given steps.
+    pub fn screens_rgb(&mut self, steps: Range<u64>) -> Result<Vec<Vec<u8>>, Error> {
+        self.collect(steps, |game, buf| game.screen_rgb_in_buf(buf))
+    }
+
+    /// The RAM after each of the given steps.
+    pub fn ram(&mut self, steps: Range<u64>) -> Result<Vec<Vec<u8>>, Error> {
+        self.collect(steps, |game, buf| game.ram_in_buf(buf))
+    }
+
+    fn collect<F: Fn(&Game, &mut Vec<u8>)>(&mut self, steps: Range<u64>, read: F) -> Result<Vec<Vec<u8>>, Error> {
+        let mut out = Vec::new();
+        try!(self.for_each(steps, |_, _, game| {
+            let mut buf = Vec::new();
+            read(game, &mut buf);
+            out.push(buf);
+        }));
+
+        Ok(out)
+    }
+
+    pub fn into_game(self) -> Game {
+        self.game
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{Error,Settings};
+    use ::seed::EpisodeSeed;
+    use super::*;
+
+    fn replay() -> CompactReplay {
+        CompactReplay {
+            rom_md5: Some([3; 16]),
+            rom_path: "roms/pong.bin".to_owned(),
+            settings: Settings {
+                random_seed: 0,
+                frame_skip: 1,
+                max_num_frames: 0,
+                max_num_frames_per_episode: 0,
+                repeat_action_probability: 0.0,
+                color_averaging: false,
+                display_screen: false,
+                sound: false,
+                record_screen_dir: String::new(),
+            },
+            seed: Some(EpisodeSeed { master: 1, env_id: 2, episode: 0, seed: 4 }),
+            initial_state: None,
+            checkpoint_interval: 0,
+            actions: vec![0, 1, 3, 4, 0, 2],
+            resets: vec![2, 4, 4],
+            checkpoints: Vec::new(),
+        }
+    }
+
+    fn encoded(replay: &CompactReplay) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        replay.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Where the checkpoint interval is in `encoded(&replay())`.
+    fn interval_offset(bytes: &[u8]) -> usize {
+        // interval, action count, 6 actions, reset count, 3 resets,
+        // checkpoint count
+        bytes.len() - (8 + 8 + 6 + 8 + 3 * 8 + 8)
+    }
+
+    #[test]
+    fn round_trips() {
+        let bytes = encoded(&replay());
+        let read = CompactReplay::read_from(&mut &bytes[..]).unwrap();
+
+        assert_eq!(read.rom_md5, Some([3; 16]));
+        assert_eq!(read.rom_path, "roms/pong.bin");
+        assert_eq!(read.seed, replay().seed);
+        assert!(read.initial_state.is_none());
+        assert_eq!(read.actions, replay().actions);
+        assert_eq!(read.resets(), &[2, 4, 4]);
+        assert_eq!(read.episodes(), 4);
+        assert_eq!(encoded(&read), bytes);
+    }
+
+    #[test]
+    fn truncated_replays_are_rejected() {
+        let bytes = encoded(&replay());
+        for len in 0..bytes.len() {
+            assert!(CompactReplay::read_from(&mut &bytes[..len]).is_err(), "read a replay cut at {}", len);
+        }
+    }
+
+    #[test]
+    fn huge_action_counts_fail_at_the_end_of_the_input() {
+        let mut bytes = encoded(&replay());
+        let actions = interval_offset(&bytes) + 8;
+        assert_eq!(&bytes[actions..actions + 8], &[6, 0, 0, 0, 0, 0, 0, 0]);
+
+        bytes[actions..actions + 8].copy_from_slice(&[0, 0, 0, 0x7f, 0, 0, 0, 0]);
+        assert!(CompactReplay::read_from(&mut &bytes[..]).is_err());
+        bytes[actions..actions + 8].copy_from_slice(&[0xff; 8]);
+        match CompactReplay::read_from(&mut &bytes[..]) {
+            Err(Error::Format(_)) => {},
+            _ => panic!("expected a format error"),
+        }
+    }
+
+    #[test]
+    fn checkpoint_counts_are_bounded_by_the_interval() {
+        let mut bytes = encoded(&replay());
+        let count = bytes.len() - 8;
+        bytes[count..].copy_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        match CompactReplay::read_from(&mut &bytes[..]) {
+            Err(Error::Format(ref message)) => assert!(message.contains("at most 0")),
+            _ => panic!("expected a format error"),
+        }
+
+        // An interval of 4 leaves room for one checkpoint in 6 steps.
+        let interval = interval_offset(&bytes);
+        bytes[interval..interval + 8].copy_from_slice(&[4, 0, 0, 0, 0, 0, 0, 0]);
+        bytes[count..].copy_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0]);
+        match CompactReplay::read_from(&mut &bytes[..]) {
+            Err(Error::Format(ref message)) => assert!(message.contains("at most 1")),
+            _ => panic!("expected a format error"),
+        }
+    }
+
+    #[test]
+    fn resets_out_of_order_are_rejected() {
+        let mut bytes = encoded(&replay());
+        let first_reset = bytes.len() - 8 - 8 * 3;
+        bytes[first_reset] = 5;
+        assert!(CompactReplay::read_from(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn resets_at_counts_repeats() {
+        let resets = [2, 4, 4, 9];
+        assert_eq!(resets_at(&resets, 0), 0);
+        assert_eq!(resets_at(&resets, 2), 1);
+        assert_eq!(resets_at(&resets, 3), 0);
+        assert_eq!(resets_at(&resets, 4), 2);
+        assert_eq!(resets_at(&resets, 9), 1);
+        assert_eq!(resets_at(&resets, 10), 0);
+    }
+}
@@ -0,0 +1,60 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors reported by this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// A serialized state was malformed or ALE failed to decode it.
+    StateDecode(&'static str),
+    Io(io::Error),
+    /// No ROM with the given id was found.
+    UnknownRom(String),
+    /// Encoded data, such as a save game, was malformed.
+    Format(String),
+    /// Settings that affect determinism were changed from the values a game
+    /// was saved with.
+    SettingsOverridden(Vec<&'static str>),
+    /// A state cloned from one ROM was restored into a game running another.
+    RomMismatch { state: [u8; 16], game: [u8; 16] },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::StateDecode(reason) => write!(f, "could not decode ALE state: {}", reason),
+            Error::Io(ref err) => write!(f, "I/O error: {}", err),
+            Error::UnknownRom(ref id) => write!(f, "no ROM found for {:?}", id),
+            Error::Format(ref reason) => write!(f, "malformed data: {}", reason),
+            Error::SettingsOverridden(ref keys) => {
+                write!(f, "settings that affect determinism were overridden: {}", keys.join(", "))
+            },
+            Error::RomMismatch { ref state, ref game } => {
+                write!(f, "state belongs to ROM {} but the game is running ROM {}", hex(state), hex(game))
+            },
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::StateDecode(_) => "could not decode ALE state",
+            Error::Io(_) => "I/O error",
+            Error::UnknownRom(_) => "no ROM found",
+            Error::Format(_) => "malformed data",
+            Error::SettingsOverridden(_) => "settings that affect determinism were overridden",
+            Error::RomMismatch { .. } => "state belongs to a different ROM",
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
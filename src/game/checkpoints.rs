@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use super::AleState;
+
+/// A store of any number of cloned states, kept both as a stack and by
+/// name. Unlike `Game::save_state`, which uses ALE's single internal slot,
+/// saving a checkpoint never overwrites another one unless it reuses its
+/// name. Use it through the `*_checkpoint` methods on `Game`.
+pub struct Checkpoints {
+    stack: Vec<AleState>,
+    named: BTreeMap<String, AleState>,
+}
+
+impl Checkpoints {
+    pub fn new() -> Self {
+        Checkpoints {
+            stack: Vec::new(),
+            named: BTreeMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, state: AleState) {
+        self.stack.push(state);
+    }
+
+    pub fn pop(&mut self) -> Option<AleState> {
+        self.stack.pop()
+    }
+
+    pub fn peek(&self) -> Option<&AleState> {
+        self.stack.last()
+    }
+
+    /// The number of states on the stack.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Stores a state under the given name, returning the state it replaced.
+    pub fn insert(&mut self, name: &str, state: AleState) -> Option<AleState> {
+        self.named.insert(name.to_owned(), state)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AleState> {
+        self.named.get(name)
+    }
+
+    pub fn delete(&mut self, name: &str) -> Option<AleState> {
+        self.named.remove(name)
+    }
+
+    /// The names of all named states, in sorted order.
+    pub fn names(&self) -> Vec<&str> {
+        self.named.keys().map(|name| &**name).collect()
+    }
+
+    /// The total number of stacked and named states.
+    pub fn len(&self) -> usize {
+        self.stack.len() + self.named.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear();
+        self.named.clear();
+    }
+
+    /// The number of bytes taken by the serialized form of every stored
+    /// state. This approximates the memory held by the C library.
+    pub fn memory_usage(&self) -> usize {
+        self.stack.iter().chain(self.named.values()).map(|s| s.encoded_len()).sum()
+    }
+}
+
+impl Default for Checkpoints {
+    fn default() -> Self {
+        Checkpoints::new()
+    }
+}
@@ -4,19 +4,70 @@ use std::ops::{Deref,DerefMut};
 use std::ffi::CString;
 use ::Action;
 use ::Ale;
+use ::Error;
+use ::seed::{self,EpisodeSeed};
 
+#[cfg(feature="rustc-serialize")]
 use ::rustc_serialize::{Encoder,Encodable,Decoder,Decodable};
+#[cfg(feature="serde")]
+use ::serde::{Serialize,Serializer,Deserialize,Deserializer};
 use ::ffi::*;
 
 pub mod serialize;
 
+mod checkpoints;
 mod state;
+pub use self::checkpoints::Checkpoints;
 pub use self::state::{AleState,AleSystemState};
 use self::state::protected::Protected;
 
 pub struct Game {
     ale: Ale,
     rom_path: String,
+    seed: Option<EpisodeSeed>,
+    checkpoints: Checkpoints,
+    rom_md5: Option<[u8; 16]>,
+}
+
+/// What happened during a single call to `Game::step`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StepInfo {
+    pub reward: i32,
+    pub lives: i32,
+    pub frame_number: i32,
+    pub episode_frame_number: i32,
+    pub terminal: bool,
+    /// Whether the previous action was repeated instead of the requested
+    /// one, see `StickyActions`.
+    pub sticky: bool,
+    /// The seed of the current episode, if the game was loaded with
+    /// `Ale::load_rom_seeded`.
+    pub seed: Option<EpisodeSeed>,
+}
+
+/// A game, or a wrapper around one such as `StickyActions`, that can be
+/// stepped and reset. Recording and replay tools are generic over it.
+pub trait Env {
+    /// Performs an action, see `Game::step`.
+    fn step(&mut self, action: Action) -> StepInfo;
+    /// Starts a new episode, see `Game::reset`.
+    fn reset(&mut self);
+    /// The game being stepped.
+    fn game(&self) -> &Game;
+}
+
+impl Env for Game {
+    fn step(&mut self, action: Action) -> StepInfo {
+        Game::step(self, action)
+    }
+
+    fn reset(&mut self) {
+        Game::reset(self)
+    }
+
+    fn game(&self) -> &Game {
+        self
+    }
 }
 
 unsafe impl Send for Game {}
@@ -35,6 +86,9 @@ impl Game {
             let file_name = CString::new(file_name).unwrap();
             loadROM(self.ale.p, file_name.as_ptr());
         }
+
+        self.rom_path = file_name.to_owned();
+        self.rom_md5 = rom_md5(file_name);
     }
 
     pub fn act(&mut self, action: Action) -> i32 {
@@ -45,10 +99,35 @@ impl Game {
         }
     }
 
+    /// Acts like `act`, but also reports the state of the game after the
+    /// action.
+    pub fn step(&mut self, action: Action) -> StepInfo {
+        let reward = self.act(action);
+
+        StepInfo {
+            reward: reward,
+            lives: self.lives(),
+            frame_number: self.frame_number(),
+            episode_frame_number: self.episode_frame_number(),
+            terminal: self.is_over(),
+            sticky: false,
+            seed: self.seed,
+        }
+    }
+
+    /// The seed of the current episode, if the game was loaded with
+    /// `Ale::load_rom_seeded`.
+    pub fn seed(&self) -> Option<EpisodeSeed> {
+        self.seed
+    }
+
     pub unsafe fn from_raw_ptr(p: *mut AleInterface) -> Self {
         Game {
             ale: Ale::from_raw_ptr(p),
             rom_path: "".to_owned(),
+            seed: None,
+            checkpoints: Checkpoints::new(),
+            rom_md5: None,
         }
     }
 
@@ -62,9 +141,25 @@ impl Game {
 
     /// Resets the current game. This is equivalent to the C API wrapper's
     /// reset_game function.
+    ///
+    /// If the game is seeded, this instead moves on to the next episode seed
+    /// and reloads the ROM with it, since ALE only reads `random_seed` when
+    /// a ROM is loaded. Note that this also resets `frame_number`.
     pub fn reset(&mut self) {
-        unsafe {
-            reset_game(self.ale.p);
+        match self.seed {
+            Some(current) => {
+                let next = current.next_episode();
+                self.seed = Some(next);
+                self.ale.set_int("random_seed", seed::ale_seed(next.seed));
+
+                unsafe {
+                    let file_name = CString::new(&*self.rom_path).unwrap();
+                    loadROM(self.ale.p, file_name.as_ptr());
+                }
+            },
+            None => unsafe {
+                reset_game(self.ale.p);
+            },
         }
     }
 
@@ -133,15 +228,10 @@ impl Game {
     }
 
     pub fn screen_in_buf(&self, buf: &mut Vec<u8>) {
-        unsafe {
-            let (width, height) = self.screen_dimensions();
-            let cap = buf.capacity();
-            if cap < (width * height) as usize {
-                buf.reserve_exact((width * height) as usize - cap);
-            }
-
-            buf.set_len((width * height) as usize);
+        let (width, height) = self.screen_dimensions();
+        buf.resize((width * height) as usize, 0);
 
+        unsafe {
             getScreen(self.ale.p, buf.as_mut_ptr());
         }
     }
@@ -156,15 +246,10 @@ impl Game {
     }
 
     pub fn screen_rgb_in_buf(&self, buf: &mut Vec<u8>) {
-        unsafe {
-            let (width, height) = self.screen_dimensions();
-            let cap = buf.capacity();
-            if cap < (width * height) as usize {
-                buf.reserve_exact((width * height * 3) as usize - cap);
-            }
-
-            buf.set_len((width * height * 3) as usize);
+        let (width, height) = self.screen_dimensions();
+        buf.resize((width * height * 3) as usize, 0);
 
+        unsafe {
             getScreenRGB(self.ale.p, buf.as_mut_ptr());
         }
     }
@@ -185,15 +270,10 @@ impl Game {
     }
 
     pub fn ram_in_buf(&self, buf: &mut Vec<u8>) {
-        unsafe {
-            let size = self.ram_size() as usize;
-            let cap = buf.capacity();
-            if cap < size {
-                buf.reserve_exact(size - cap);
-            }
-
-            buf.set_len(size);
+        let size = self.ram_size() as usize;
+        buf.resize(size, 0);
 
+        unsafe {
             getRAM(self.ale.p, buf.as_mut_ptr());
         }
     }
@@ -207,6 +287,8 @@ impl Game {
         buf
     }
 
+    /// Saves the state into ALE's single internal slot, overwriting any
+    /// earlier save. Use the checkpoint methods to keep several states.
     pub fn save_state(&mut self) {
         unsafe {
             saveState(self.ale.p);
@@ -227,43 +309,141 @@ impl Game {
         }
     }
 
+    /// The MD5 of the loaded ROM file, if it could be read.
+    pub fn rom_md5(&self) -> Option<[u8; 16]> {
+        self.rom_md5
+    }
+
+    /// Clones the current state, tagged with the MD5 of the loaded ROM.
     pub fn clone_state(&self) -> AleState {
-        unsafe { AleState::new(cloneState(self.ale.p)) }
+        unsafe { AleState::new(cloneState(self.ale.p), self.rom_md5) }
     }
 
+    /// Clones the current system state, tagged with the MD5 of the loaded ROM.
     pub fn clone_system_state(&self) -> AleSystemState {
-        unsafe { AleSystemState::new(cloneSystemState(self.ale.p)) }
+        unsafe { AleSystemState::new(cloneSystemState(self.ale.p), self.rom_md5) }
     }
 
-    pub fn restore_from_cloned_state(&mut self, s: &AleState) {
+    /// Restores a cloned state. Fails without touching the emulator if the
+    /// state is tagged with a different ROM than the one loaded. Untagged
+    /// states, e.g. from legacy save games or `set_rom_md5(None)`, are always
+    /// restored.
+    pub fn restore_from_cloned_state(&mut self, s: &AleState) -> Result<(), Error> {
+        try!(self.check_rom(s.rom_md5()));
+
         unsafe {
             restoreState(self.ale.p, s.s());
         }
+
+        Ok(())
     }
 
-    pub fn restore_from_cloned_system_state(&mut self, s: &AleSystemState) {
+    /// Restores a cloned system state, with the same ROM check as
+    /// `restore_from_cloned_state`.
+    pub fn restore_from_cloned_system_state(&mut self, s: &AleSystemState) -> Result<(), Error> {
+        try!(self.check_rom(s.rom_md5()));
+
         unsafe {
             restoreSystemState(self.ale.p, s.s());
         }
+
+        Ok(())
     }
 
+    fn check_rom(&self, state_md5: Option<[u8; 16]>) -> Result<(), Error> {
+        match (state_md5, self.rom_md5) {
+            (Some(state), Some(game)) if state != game => Err(Error::RomMismatch { state: state, game: game }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Clones the current state onto the checkpoint stack.
+    pub fn push_checkpoint(&mut self) {
+        let state = self.clone_state();
+        self.checkpoints.push(state);
+    }
+
+    /// Restores the state on top of the checkpoint stack and removes it.
+    /// Returns false if the stack was empty. A checkpoint from another ROM
+    /// is an error and stays on the stack.
+    pub fn pop_checkpoint(&mut self) -> Result<bool, Error> {
+        let (state, state_md5) = match self.checkpoints.peek() {
+            Some(state) => (state.s(), state.rom_md5()),
+            None => return Ok(false),
+        };
+        try!(self.check_rom(state_md5));
+
+        unsafe {
+            restoreState(self.ale.p, state);
+        }
+        self.checkpoints.pop();
+
+        Ok(true)
+    }
+
+    /// Clones the current state into a checkpoint with the given name,
+    /// replacing any checkpoint already using it.
+    pub fn save_checkpoint(&mut self, name: &str) {
+        let state = self.clone_state();
+        self.checkpoints.insert(name, state);
+    }
+
+    /// Restores the named checkpoint, which is kept for later restores.
+    /// Returns false if there is no such checkpoint.
+    pub fn restore_checkpoint(&mut self, name: &str) -> Result<bool, Error> {
+        let (state, state_md5) = match self.checkpoints.get(name) {
+            Some(state) => (state.s(), state.rom_md5()),
+            None => return Ok(false),
+        };
+        try!(self.check_rom(state_md5));
+
+        unsafe {
+            restoreState(self.ale.p, state);
+        }
+
+        Ok(true)
+    }
+
+    pub fn delete_checkpoint(&mut self, name: &str) -> bool {
+        self.checkpoints.delete(name).is_some()
+    }
+
+    pub fn checkpoints(&self) -> &Checkpoints {
+        &self.checkpoints
+    }
+
+    pub fn checkpoints_mut(&mut self) -> &mut Checkpoints {
+        &mut self.checkpoints
+    }
+
+    /// The path the ROM was loaded from.
+    pub fn rom_path(&self) -> &str {
+        &self.rom_path
+    }
+
+    /// Reads the ROM file back from `rom_path`.
     pub fn rom(&self) -> serialize::Rom {
         serialize::Rom::new(&self.rom_path)
     }
 }
 
+#[cfg(feature="rustc-serialize")]
 impl Encodable for Game {
     fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-        use self::serialize::Rom;
-        
-        try!(self.rom_path.encode(s));
-        let rom = Rom::new(&self.rom_path);
-        try!(rom.encode(s));
+        use self::serialize::SaveHeader;
+
+        let rom = self.rom();
+        let header = SaveHeader::new(&self.ale, &rom);
 
-        self.clone_system_state().encode(s)
+        s.emit_struct("SaveGame", 3, |s| {
+            try!(s.emit_struct_field("header", 0, |s| header.encode(s)));
+            try!(s.emit_struct_field("rom", 1, |s| rom.encode(s)));
+            s.emit_struct_field("state", 2, |s| self.clone_system_state().encode(s))
+        })
     }
 }
 
+#[cfg(feature="rustc-serialize")]
 impl Decodable for Game {
     fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
         use self::serialize::GameDecoder;
@@ -272,6 +452,32 @@ impl Decodable for Game {
     }
 }
 
+#[cfg(feature="serde")]
+impl Serialize for Game {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use ::serde::ser::SerializeStruct;
+        use self::serialize::SaveHeader;
+
+        let rom = self.rom();
+        let header = SaveHeader::new(&self.ale, &rom);
+
+        let mut save = try!(s.serialize_struct("SaveGame", 3));
+        try!(save.serialize_field("header", &header));
+        try!(save.serialize_field("rom", &rom));
+        try!(save.serialize_field("state", &self.clone_system_state()));
+        save.end()
+    }
+}
+
+#[cfg(feature="serde")]
+impl<'de> Deserialize<'de> for Game {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use self::serialize::GameDecoder;
+        let prelim = try!(GameDecoder::deserialize(d));
+        Ok(prelim.decode_game())
+    }
+}
+
 impl Into<Ale> for Game {
     fn into(self) -> Ale {
         self.ale
@@ -293,17 +499,34 @@ impl DerefMut for Game {
 }
 
 
+fn rom_md5(path: &str) -> Option<[u8; 16]> {
+    ::bytes::file_digest(path.as_ref(), u64::max_value()).ok()
+}
+
 pub mod protected {
     use ::Ale;
-    use super::Game;
+    use ::seed::EpisodeSeed;
+    use super::{Checkpoints,Game,rom_md5};
 
     pub trait Protected {
         fn new(ale: Ale, path: String) -> Self;
+        fn new_seeded(ale: Ale, path: String, seed: EpisodeSeed) -> Self;
+        fn set_seed(&mut self, seed: Option<EpisodeSeed>);
     } 
 
     impl Protected for Game {
         fn new(ale: Ale, path: String) -> Self {
-            Game { ale: ale, rom_path: path }
+            let md5 = rom_md5(&path);
+            Game { ale: ale, rom_path: path, seed: None, checkpoints: Checkpoints::new(), rom_md5: md5 }
+        }
+
+        fn new_seeded(ale: Ale, path: String, seed: EpisodeSeed) -> Self {
+            let md5 = rom_md5(&path);
+            Game { ale: ale, rom_path: path, seed: Some(seed), checkpoints: Checkpoints::new(), rom_md5: md5 }
+        }
+
+        fn set_seed(&mut self, seed: Option<EpisodeSeed>) {
+            self.seed = seed;
         }
     }
 }
\ No newline at end of file
@@ -1,22 +1,145 @@
+//! Saving and restoring whole games.
+//!
+//! `Game` encodes to a self-describing container, which any rustc-serialize
+//! encoder or, with the `serde` feature, any serde serializer can write:
+//!
+//! ```text
+//! SaveGame {
+//!     header: SaveHeader {
+//!         magic: "ALE-SAVE",
+//!         format_version: 1,
+//!         crate_version: the version of this crate that wrote the file,
+//!         rom_md5: lowercase hex MD5 of the ROM data,
+//!         settings: the Settings of the ALE at save time,
+//!     },
+//!     rom: Rom { rom_path, data },
+//!     state: AleSystemState,
+//! }
+//! ```
+//!
+//! `GameDecoder` checks the magic and dispatches on `format_version`, so
+//! future versions can keep reading older files.
+//!
+//! Two older layouts without a header exist. Up to version 0.2.1 of this
+//! crate, `Game` encoded its ROM path, its `Rom` and its state back to back.
+//! With rustc-serialize, `GameDecoder` falls back to that layout when the
+//! first string isn't the magic, and reports it as `format_version` 0 with
+//! ALE's default settings. This only works for encodings without field
+//! names, such as `ale::binary`. Legacy JSON files hold three separate
+//! values, which `ale::migrate` can convert. Files from before that, with the
+//! ROM path, the ROM data and the state back to back, can be read with
+//! `LegacyGameDecoder`.
 use std::path::{Path,PathBuf};
 use std::convert::AsRef;
 use std::fs::{File};
-use std::io::Write;
+#[cfg(feature="rustc-serialize")]
 use rustc_serialize::{Decoder,Decodable};
-use ::{Ale,AleSystemState};
+#[cfg(feature="serde")]
+use serde::{Deserialize,Deserializer};
+use ::{Ale,AleSystemState,Error,RomCache,Settings,SettingChange};
 use super::Game;
 
+pub const SAVE_MAGIC: &'static str = "ALE-SAVE";
+pub const SAVE_FORMAT_VERSION: u32 = 1;
+
+/// The header at the start of every save game.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct SaveHeader {
+	pub magic: String,
+	pub format_version: u32,
+	pub crate_version: String,
+	pub rom_md5: String,
+	pub settings: Settings,
+}
+
+#[cfg(feature="rustc-serialize")]
+rustc_serialize_struct!(SaveHeader { magic, format_version, crate_version, rom_md5, settings });
+
+impl SaveHeader {
+	pub fn new(ale: &Ale, rom: &Rom) -> Self {
+		SaveHeader {
+			magic: SAVE_MAGIC.to_owned(),
+			format_version: SAVE_FORMAT_VERSION,
+			crate_version: env!("CARGO_PKG_VERSION").to_owned(),
+			rom_md5: rom.md5(),
+			settings: Settings::capture(ale),
+		}
+	}
+
+	/// Checks that this is the header of a save game this crate can read.
+	#[cfg(any(feature="rustc-serialize", feature="serde"))]
+	fn check(&self) -> Result<(), String> {
+		if self.magic != SAVE_MAGIC {
+			return Err("not an ALE save game".to_owned());
+		}
+		if self.format_version == 0 || self.format_version > SAVE_FORMAT_VERSION {
+			return Err(format!("unsupported save game format version {}", self.format_version));
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(any(feature="rustc-serialize", feature="serde"))]
+fn check_rom(header: &SaveHeader, rom: &Rom) -> Result<(), String> {
+	if rom.md5() != header.rom_md5 {
+		return Err("ROM data does not match the MD5 in the save game header".to_owned());
+	}
+
+	Ok(())
+}
+
 /// A GameDecoder allows you to set ALE properties before
-/// restoring a decoded game state. Note that altering some properties
-/// such as repeat_action_probability may affect replays. It's recommended
-/// to only use this to alter things like display_screen.
+/// restoring a decoded game state. It starts out with the settings the game
+/// was saved with, except display and recording settings such as
+/// display_screen, which are left at ALE's defaults. Altering some properties
+/// such as repeat_action_probability may affect replays: `overrides` lists
+/// what was changed and `check_determinism` fails on changes that affect
+/// determinism. It's recommended to only use this to alter things like
+/// display_screen.
 pub struct GameDecoder {
+	pub header: SaveHeader,
 	pub rom: Rom,
 	ale: Ale,
 	initial_state: AleSystemState,
 }
 
 impl GameDecoder {
+	#[cfg(any(feature="rustc-serialize", feature="serde"))]
+	fn new(header: SaveHeader, rom: Rom, initial_state: AleSystemState) -> Self {
+		let mut ale = Ale::new();
+		header.settings.apply_deterministic(&mut ale);
+
+		GameDecoder{
+			header: header,
+			rom: rom,
+			ale: ale,
+			initial_state: initial_state,
+		}
+	}
+
+	/// A decoder for a file without a header, whose settings are unknown.
+	/// Its header has `format_version` 0 and ALE's default settings.
+	#[cfg(feature="rustc-serialize")]
+	fn unversioned(rom: Rom, initial_state: AleSystemState) -> Self {
+		let ale = Ale::new();
+		let header = SaveHeader {
+			magic: SAVE_MAGIC.to_owned(),
+			format_version: 0,
+			crate_version: String::new(),
+			rom_md5: rom.md5(),
+			settings: Settings::capture(&ale),
+		};
+
+		GameDecoder{
+			header: header,
+			rom: rom,
+			ale: ale,
+			initial_state: initial_state,
+		}
+	}
+
 	pub fn get_int(&self, key: &str) -> i32 {
 		self.ale.get_int(key)
 	}
@@ -49,32 +172,134 @@ impl GameDecoder {
 		self.ale.set_float(key, val);
 	}
 
+	/// The settings that now differ from the ones the game was saved with.
+	/// Display and recording settings that weren't restored are included
+	/// if they differ from ALE's defaults.
+	pub fn overrides(&self) -> Vec<SettingChange> {
+		self.header.settings.diff(&Settings::capture(&self.ale))
+	}
+
+	/// Fails if any override affects determinism, for callers that would
+	/// rather not decode such a game at all.
+	pub fn check_determinism(&self) -> Result<(), Error> {
+		let keys: Vec<&'static str> = self.overrides().into_iter()
+			.filter(|change| change.affects_determinism())
+			.map(|change| change.key)
+			.collect();
+
+		if keys.is_empty() {
+			Ok(())
+		} else {
+			Err(Error::SettingsOverridden(keys))
+		}
+	}
+
+	/// Decodes the game, storing its ROM in `RomCache::from_env`.
 	pub fn decode_game(self) -> Game {
-		let backup_path = self.rom.create();
+		self.decode_game_in(&RomCache::from_env())
+	}
 
-		let mut game = self.ale.load_rom(backup_path.to_str().expect("Could not decode path to string"));
-		game.restore_from_cloned_system_state(&self.initial_state);
+	/// Decodes the game, storing its ROM in the given cache. The ROM file
+	/// must outlive the game if it is reloaded, e.g. by a seeded reset.
+	/// Overrides aren't checked, call `check_determinism` first to reject
+	/// them.
+	pub fn decode_game_in(self, cache: &RomCache) -> Game {
+		self.try_decode_game_in(cache).expect("Could not decode the game")
+	}
 
-		game
+	/// Like `decode_game_in`, but fails instead of panicking if the ROM
+	/// can't be written or the state can't be restored.
+	pub fn try_decode_game_in(self, cache: &RomCache) -> Result<Game, Error> {
+		let backup_path = try!(self.rom.create_in(cache));
+
+		load_and_restore(self.ale, &backup_path, &self.initial_state)
 	}
 }
 
+/// What a rustc-serialize save game starts with.
+#[cfg(feature="rustc-serialize")]
+enum Layout {
+	Versioned(SaveHeader),
+	/// A file written before the header existed, which starts with the ROM
+	/// path instead. The `Rom` that follows has the path too.
+	Unversioned,
+}
+
+#[cfg(feature="rustc-serialize")]
+fn decode_layout<D: Decoder>(d: &mut D) -> Result<Layout, D::Error> {
+	d.read_struct("SaveHeader", 5, |d| {
+		let magic: String = try!(d.read_struct_field("magic", 0, String::decode));
+		if magic != SAVE_MAGIC {
+			return Ok(Layout::Unversioned);
+		}
+
+		Ok(Layout::Versioned(SaveHeader {
+			magic: magic,
+			format_version: try!(d.read_struct_field("format_version", 1, u32::decode)),
+			crate_version: try!(d.read_struct_field("crate_version", 2, String::decode)),
+			rom_md5: try!(d.read_struct_field("rom_md5", 3, String::decode)),
+			settings: try!(d.read_struct_field("settings", 4, Settings::decode)),
+		}))
+	})
+}
+
+#[cfg(feature="rustc-serialize")]
 impl Decodable for GameDecoder {
 	fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-		Ok(GameDecoder{
-			rom: try!(Rom::decode(d)),
-			ale: Ale::new(),
-			initial_state: try!(AleSystemState::decode(d)),
+		d.read_struct("SaveGame", 3, |d| {
+			let header = match try!(d.read_struct_field("header", 0, decode_layout)) {
+				Layout::Versioned(header) => header,
+				Layout::Unversioned => {
+					let rom = try!(Rom::decode(d));
+					let initial_state = try!(AleSystemState::decode(d));
+
+					return Ok(GameDecoder::unversioned(rom, initial_state));
+				},
+			};
+			try!(header.check().map_err(|err| d.error(&err)));
+
+			// Version 1 is the only versioned layout so far, later versions
+			// branch here.
+			let rom = try!(d.read_struct_field("rom", 1, Rom::decode));
+			try!(check_rom(&header, &rom).map_err(|err| d.error(&err)));
+
+			let initial_state = try!(d.read_struct_field("state", 2, AleSystemState::decode));
+
+			Ok(GameDecoder::new(header, rom, initial_state))
 		})
 	}
 }
 
-#[derive(RustcEncodable,RustcDecodable)]
+#[cfg(feature="serde")]
+#[derive(Deserialize)]
+struct SaveGame {
+	header: SaveHeader,
+	rom: Rom,
+	state: AleSystemState,
+}
+
+#[cfg(feature="serde")]
+impl<'de> Deserialize<'de> for GameDecoder {
+	fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+		use serde::de::Error;
+
+		let save = try!(SaveGame::deserialize(d));
+		try!(save.header.check().map_err(D::Error::custom));
+		try!(check_rom(&save.header, &save.rom).map_err(D::Error::custom));
+
+		Ok(GameDecoder::new(save.header, save.rom, save.state))
+	}
+}
+
+#[cfg_attr(feature="serde", derive(Serialize,Deserialize))]
 pub struct Rom {
 	pub rom_path: PathBuf,
 	pub data: Vec<u8>,
 }
 
+#[cfg(feature="rustc-serialize")]
+rustc_serialize_struct!(Rom { rom_path, data });
+
 impl Rom {
 	pub fn new<P: AsRef<Path>>(p: P) -> Self {
 		use std::io::Read;
@@ -90,27 +315,24 @@ impl Rom {
         }
 	}
 
-	pub fn create(&self) -> PathBuf {
-		use std::fs;
-
-		let filename = self.rom_path.file_name().expect("Rom didn't have an actual file name?")
-						   .to_str().expect("Filename was not valid unicode");
-
-		let backup_path = Path::new(&format!("./ROMs/{}", filename)).to_path_buf();
+	/// The lowercase hex MD5 digest of the ROM data.
+	pub fn md5(&self) -> String {
+		format!("{:x}", ::md5::compute(&self.data))
+	}
 
-		if !backup_path.exists() {	
-			fs::create_dir_all("./ROMs").expect("Could not create ROM directory");
-			match File::create(&backup_path) {
-					Ok(mut file) => { file.write_all(self.data.as_slice()).expect("Could not find or write ROM file") },
-					Err(err) => panic!(err),
-			};
-		};
+	/// Writes the ROM to `RomCache::from_env` and returns its path.
+	pub fn create(&self) -> PathBuf {
+		self.create_in(&RomCache::from_env()).expect("Could not write ROM file to the cache")
+	}
 
-		backup_path
+	/// Writes the ROM to the given cache and returns its path.
+	pub fn create_in(&self, cache: &RomCache) -> Result<PathBuf, Error> {
+		cache.store(self)
 	}
 }
 
-/// The old game decoder
+/// The old game decoder, for files written before the versioned save game
+/// format: the ROM path, the ROM data and the system state back to back.
 pub struct LegacyGameDecoder {
 	pub rom_path: PathBuf,
 	ale: Ale,
@@ -119,6 +341,16 @@ pub struct LegacyGameDecoder {
 }
 
 impl LegacyGameDecoder {
+	/// Creates a decoder from parts that were decoded separately.
+	pub fn from_parts(rom_path: PathBuf, romfile: Vec<u8>, initial_state: AleSystemState) -> Self {
+		LegacyGameDecoder{
+			rom_path: rom_path,
+			romfile: romfile,
+			ale: Ale::new(),
+			initial_state: initial_state,
+		}
+	}
+
 	pub fn get_int(&self, key: &str) -> i32 {
 		self.ale.get_int(key)
 	}
@@ -151,37 +383,149 @@ impl LegacyGameDecoder {
 		self.ale.set_float(key, val);
 	}
 
+	/// Decodes the game, storing its ROM in `RomCache::from_env`.
 	pub fn decode_game(self) -> Game {
-		use std::fs;
+		self.decode_game_in(&RomCache::from_env())
+	}
 
-		let path = self.rom_path.as_path();
-		let filename = path.file_name().expect("Rom didn't have an actual file name?")
-						   .to_str().expect("Filename was not valid unicode");
+	/// Decodes the game, storing its ROM in the given cache.
+	pub fn decode_game_in(self, cache: &RomCache) -> Game {
+		self.try_decode_game_in(cache).expect("Could not decode the game")
+	}
 
-		let backup_path = format!("./ROMs/{}", filename);
+	/// Like `decode_game_in`, but fails instead of panicking if the ROM
+	/// can't be written or the state can't be restored.
+	pub fn try_decode_game_in(self, cache: &RomCache) -> Result<Game, Error> {
+		let rom = Rom {
+			rom_path: self.rom_path,
+			data: self.romfile,
+		};
+		let backup_path = try!(rom.create_in(cache));
 
-		if !path.exists() {	
-			fs::create_dir_all("./ROMs").expect("Could not create ROM directory");
-			match File::create(&backup_path) {
-					Ok(mut file) => { file.write_all(self.romfile.as_slice()).expect("Could not find or write ROM file") },
-					Err(err) => panic!(err),
-			};
-		}
+		load_and_restore(self.ale, &backup_path, &self.initial_state)
+	}
+}
 
-		let mut game = self.ale.load_rom(&backup_path);
-		game.restore_from_cloned_system_state(&self.initial_state);
+/// Loads the ROM at `path` and restores `state` into it.
+fn load_and_restore(ale: Ale, path: &Path, state: &AleSystemState) -> Result<Game, Error> {
+	let path = try!(path.to_str().ok_or_else(|| Error::Format("ROM cache path isn't valid UTF-8".to_owned())));
+	let mut game = ale.load_rom(path);
+	try!(game.restore_from_cloned_system_state(state));
 
-		game
-	}
+	Ok(game)
 }
 
+#[cfg(feature="rustc-serialize")]
 impl Decodable for LegacyGameDecoder {
 	fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-		Ok(LegacyGameDecoder{
-			rom_path: Path::new(&try!(String::decode(d))).to_path_buf(),
-			romfile: try!(Vec::<u8>::decode(d)),
-			ale: Ale::new(),
-			initial_state: try!(AleSystemState::decode(d)),
-		})
+		let rom_path = Path::new(&try!(String::decode(d))).to_path_buf();
+		let romfile = try!(Vec::<u8>::decode(d));
+		let initial_state = try!(AleSystemState::decode(d));
+
+		Ok(LegacyGameDecoder::from_parts(rom_path, romfile, initial_state))
+	}
+}
+#[cfg(all(test, feature="rustc-serialize"))]
+mod tests {
+	use rustc_serialize::json;
+	use ::{binary,Settings};
+	use super::*;
+
+	fn settings() -> Settings {
+		Settings {
+			random_seed: 123,
+			frame_skip: 4,
+			max_num_frames: 0,
+			max_num_frames_per_episode: 10000,
+			repeat_action_probability: 0.25,
+			color_averaging: true,
+			display_screen: false,
+			sound: false,
+			record_screen_dir: String::new(),
+		}
+	}
+
+	fn header() -> SaveHeader {
+		SaveHeader {
+			magic: SAVE_MAGIC.to_owned(),
+			format_version: SAVE_FORMAT_VERSION,
+			crate_version: "0.2.1".to_owned(),
+			rom_md5: "d41d8cd98f00b204e9800998ecf8427e".to_owned(),
+			settings: settings(),
+		}
+	}
+
+	fn assert_same_header(a: &SaveHeader, b: &SaveHeader) {
+		assert_eq!(a.magic, b.magic);
+		assert_eq!(a.format_version, b.format_version);
+		assert_eq!(a.crate_version, b.crate_version);
+		assert_eq!(a.rom_md5, b.rom_md5);
+		assert_eq!(a.settings, b.settings);
+	}
+
+	fn layout(bytes: &[u8]) -> Layout {
+		decode_layout(&mut binary::Decoder::new(&mut &bytes[..])).unwrap()
+	}
+
+	#[test]
+	fn headers_round_trip() {
+		let bytes = binary::encode(&header()).unwrap();
+		assert_same_header(&binary::decode(&bytes).unwrap(), &header());
+
+		match layout(&bytes) {
+			Layout::Versioned(decoded) => assert_same_header(&decoded, &header()),
+			Layout::Unversioned => panic!("a header was taken for a ROM path"),
+		}
+
+		let text = json::encode(&header()).unwrap();
+		assert_same_header(&json::decode(&text).unwrap(), &header());
+	}
+
+	#[test]
+	fn unversioned_files_are_detected() {
+		// Version 0.2.1 and earlier started with the ROM path.
+		let mut bytes = binary::encode(&"roms/pong.bin".to_owned()).unwrap();
+		bytes.extend(binary::encode(&Rom { rom_path: PathBuf::from("roms/pong.bin"), data: vec![1, 2, 3] }).unwrap());
+
+		match layout(&bytes) {
+			Layout::Unversioned => {},
+			Layout::Versioned(_) => panic!("an unversioned file was taken for a versioned one"),
+		}
+	}
+
+	#[test]
+	fn roms_round_trip() {
+		let rom = Rom { rom_path: PathBuf::from("roms/pong.bin"), data: (0..=255).collect() };
+		let decoded: Rom = binary::decode(&binary::encode(&rom).unwrap()).unwrap();
+
+		assert_eq!(decoded.rom_path, rom.rom_path);
+		assert_eq!(decoded.data, rom.data);
+		assert_eq!(decoded.md5(), rom.md5());
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn headers_are_checked() {
+		assert!(header().check().is_ok());
+
+		let mut bad = header();
+		bad.magic = "ALE-SAVF".to_owned();
+		assert!(bad.check().is_err());
+
+		let mut bad = header();
+		bad.format_version = 0;
+		assert!(bad.check().is_err());
+
+		let mut bad = header();
+		bad.format_version = SAVE_FORMAT_VERSION + 1;
+		assert!(bad.check().is_err());
+	}
+
+	#[test]
+	fn rom_data_must_match_the_header() {
+		let rom = Rom { rom_path: PathBuf::from("pong.bin"), data: Vec::new() };
+		assert!(check_rom(&header(), &rom).is_ok());
+
+		let rom = Rom { rom_path: PathBuf::from("pong.bin"), data: vec![0] };
+		assert!(check_rom(&header(), &rom).is_err());
+	}
+}
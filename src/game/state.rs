@@ -1,79 +1,476 @@
 use ::libc::c_int;
+use std::hash::{Hash,Hasher};
+use std::io::{self,Read,Write};
+#[cfg(feature="rustc-serialize")]
 use ::rustc_serialize::{Encodable,Encoder,Decodable,Decoder};
+#[cfg(feature="serde")]
+use ::serde::{Serialize,Serializer,Deserialize,Deserializer};
 use ::ffi::*;
+use ::error::Error;
+use ::bytes::fnv1a;
 
 pub struct AleState {
     s: *mut CAleState,
+    rom_md5: Option<[u8; 16]>,
+}
+
+impl AleState {
+    /// Decodes a state, and its ROM tag, from bytes written by `to_bytes`.
+    /// The framing and hash are checked before anything is passed to ALE,
+    /// so truncated or corrupt bytes are an error rather than a crash.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (rom_md5, serialized) = try!(parse_state(bytes, STATE_KIND));
+
+        Ok(AleState{
+            s: try!(decode_state(&serialized)),
+            rom_md5: rom_md5,
+        })
+    }
+
+    /// The MD5 of the ROM the state was cloned from, if known. Games refuse
+    /// to restore states tagged with a different ROM.
+    pub fn rom_md5(&self) -> Option<[u8; 16]> {
+        self.rom_md5
+    }
+
+    /// Tags the state with the ROM it belongs to, or removes the tag.
+    pub fn set_rom_md5(&mut self, rom_md5: Option<[u8; 16]>) {
+        self.rom_md5 = rom_md5;
+    }
+
+    /// The state and its ROM tag in the compact, length-prefixed form
+    /// described on `frame_state`, as accepted by `from_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        frame_state(STATE_KIND, self.rom_md5, &encode_state(self.s))
+    }
+
+    /// Writes the bytes of `to_bytes`, which `read_from` reads back.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_state(w, STATE_KIND, self.rom_md5, &encode_state(self.s))
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        let (rom_md5, serialized) = try!(read_state(r, STATE_KIND));
+
+        Ok(AleState{
+            s: try!(decode_state(&serialized)),
+            rom_md5: rom_md5,
+        })
+    }
+
+    /// The length in bytes of this state's serialized form.
+    pub fn encoded_len(&self) -> usize {
+        unsafe { encodeStateLen(self.s) as usize }
+    }
+
+    /// A 64-bit FNV-1a digest of the serialized state. Equal states have
+    /// equal fingerprints, so this is a cheap key for deduplication.
+    pub fn fingerprint(&self) -> u64 {
+        fnv1a(&encode_state(self.s))
+    }
+}
+
+// The state is a heap object owned by this value alone and is not tied to
+// the ALE instance that cloned it, so it may move between threads.
+unsafe impl Send for AleState {}
+
+impl Clone for AleState {
+    fn clone(&self) -> Self {
+        AleState{
+            s: decode_state(&encode_state(self.s)).expect("ALE could not decode a state it encoded"),
+            rom_md5: self.rom_md5,
+        }
+    }
+}
+
+impl PartialEq for AleState {
+    fn eq(&self, other: &Self) -> bool {
+        encode_state(self.s) == encode_state(other.s)
+    }
+}
+
+impl Eq for AleState {}
+
+impl Hash for AleState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        encode_state(self.s).hash(state);
+    }
 }
 
 impl Drop for AleState {
     fn drop(&mut self) {
         unsafe {
-            deleteState(self.s);
+            if !self.s.is_null() {
+                deleteState(self.s);
+            }
         }
     }
 }
 
+#[cfg(feature="rustc-serialize")]
 impl Encodable for AleState {
     fn encode<S: Encoder>(&self, s: &mut S) -> Result<(),S::Error> {
         let serial = encode_state(self.s);
-        serial.encode(s)
+        as_signed(&serial).encode(s)
     }
 }
 
+#[cfg(feature="rustc-serialize")]
 impl Decodable for AleState {
     fn decode<D: Decoder>(d: &mut D) -> Result<Self,D::Error> {
-        let serial = try!(Vec::decode(d));
+        let serial: Vec<i8> = try!(Vec::decode(d));
+        let bytes: Vec<u8> = serial.into_iter().map(|b| b as u8).collect();
 
-        Ok(AleState{
-            s: decode_state(&serial),
-        })
+        match decode_state(&bytes) {
+            Ok(s) => Ok(AleState{ s: s, rom_md5: None }),
+            Err(err) => Err(d.error(&err.to_string())),
+        }
+    }
+}
+
+// States are serialized as the compact bytes of `to_bytes`, rather than a
+// sequence of numbers, and keep their ROM tag.
+#[cfg(feature="serde")]
+impl Serialize for AleState {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_bytes(&self.to_bytes())
+    }
+}
+
+#[cfg(feature="serde")]
+impl<'de> Deserialize<'de> for AleState {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use ::serde::de::Error;
+
+        let bytes = try!(d.deserialize_bytes(serde_bytes::BytesVisitor));
+        AleState::from_bytes(&bytes).map_err(D::Error::custom)
     }
 }
 
 pub struct AleSystemState {
     s: *mut CAleState,
+    rom_md5: Option<[u8; 16]>,
+}
+
+impl AleSystemState {
+    /// Decodes a state, and its ROM tag, from bytes written by `to_bytes`.
+    /// The framing and hash are checked before anything is passed to ALE,
+    /// so truncated or corrupt bytes are an error rather than a crash.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (rom_md5, serialized) = try!(parse_state(bytes, SYSTEM_STATE_KIND));
+
+        Ok(AleSystemState{
+            s: try!(decode_state(&serialized)),
+            rom_md5: rom_md5,
+        })
+    }
+
+    /// The MD5 of the ROM the state was cloned from, if known. Games refuse
+    /// to restore states tagged with a different ROM.
+    pub fn rom_md5(&self) -> Option<[u8; 16]> {
+        self.rom_md5
+    }
+
+    /// Tags the state with the ROM it belongs to, or removes the tag.
+    pub fn set_rom_md5(&mut self, rom_md5: Option<[u8; 16]>) {
+        self.rom_md5 = rom_md5;
+    }
+
+    /// The state and its ROM tag in the compact, length-prefixed form
+    /// described on `frame_state`, as accepted by `from_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        frame_state(SYSTEM_STATE_KIND, self.rom_md5, &encode_state(self.s))
+    }
+
+    /// Writes the bytes of `to_bytes`, which `read_from` reads back.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_state(w, SYSTEM_STATE_KIND, self.rom_md5, &encode_state(self.s))
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        let (rom_md5, serialized) = try!(read_state(r, SYSTEM_STATE_KIND));
+
+        Ok(AleSystemState{
+            s: try!(decode_state(&serialized)),
+            rom_md5: rom_md5,
+        })
+    }
+
+    /// The length in bytes of this state's serialized form.
+    pub fn encoded_len(&self) -> usize {
+        unsafe { encodeStateLen(self.s) as usize }
+    }
+
+    /// A 64-bit FNV-1a digest of the serialized state. Equal states have
+    /// equal fingerprints, so this is a cheap key for deduplication.
+    pub fn fingerprint(&self) -> u64 {
+        fnv1a(&encode_state(self.s))
+    }
+}
+
+// The state is a heap object owned by this value alone and is not tied to
+// the ALE instance that cloned it, so it may move between threads.
+unsafe impl Send for AleSystemState {}
+
+impl Clone for AleSystemState {
+    fn clone(&self) -> Self {
+        AleSystemState{
+            s: decode_state(&encode_state(self.s)).expect("ALE could not decode a state it encoded"),
+            rom_md5: self.rom_md5,
+        }
+    }
+}
+
+impl PartialEq for AleSystemState {
+    fn eq(&self, other: &Self) -> bool {
+        encode_state(self.s) == encode_state(other.s)
+    }
+}
+
+impl Eq for AleSystemState {}
+
+impl Hash for AleSystemState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        encode_state(self.s).hash(state);
+    }
 }
 
 impl Drop for AleSystemState {
     fn drop(&mut self) {
         unsafe {
-            deleteState(self.s)
+            if !self.s.is_null() {
+                deleteState(self.s);
+            }
         }
     }
 }
 
+#[cfg(feature="rustc-serialize")]
 impl Encodable for AleSystemState {
     fn encode<S: Encoder>(&self, s: &mut S) -> Result<(),S::Error> {
         let serial = encode_state(self.s);
-        serial.encode(s)
+        as_signed(&serial).encode(s)
     }
 }
 
+#[cfg(feature="rustc-serialize")]
 impl Decodable for AleSystemState {
     fn decode<D: Decoder>(d: &mut D) -> Result<Self,D::Error> {
-        let serial = try!(Vec::decode(d));
+        let serial: Vec<i8> = try!(Vec::decode(d));
+        let bytes: Vec<u8> = serial.into_iter().map(|b| b as u8).collect();
 
-        Ok(AleSystemState{
-            s: decode_state(&serial),
-        })
+        match decode_state(&bytes) {
+            Ok(s) => Ok(AleSystemState{ s: s, rom_md5: None }),
+            Err(err) => Err(d.error(&err.to_string())),
+        }
     }
 }
 
-fn encode_state(s: *mut CAleState) -> Vec<i8> {
+// States are serialized as the compact bytes of `to_bytes`, rather than a
+// sequence of numbers, and keep their ROM tag.
+#[cfg(feature="serde")]
+impl Serialize for AleSystemState {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_bytes(&self.to_bytes())
+    }
+}
+
+#[cfg(feature="serde")]
+impl<'de> Deserialize<'de> for AleSystemState {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use ::serde::de::Error;
+
+        let bytes = try!(d.deserialize_bytes(serde_bytes::BytesVisitor));
+        AleSystemState::from_bytes(&bytes).map_err(D::Error::custom)
+    }
+}
+
+/// Serialized states longer than this are rejected when reading, so a
+/// corrupt length can't trigger a huge allocation. ALE states are a few
+/// kilobytes.
+const MAX_STATE_LEN: usize = 1 << 20;
+
+const FORMAT_MAGIC: u8 = b'A';
+const FORMAT_VERSION: u8 = 3;
+const STATE_KIND: u8 = 0;
+const SYSTEM_STATE_KIND: u8 = 1;
+
+fn encode_state(s: *mut CAleState) -> Vec<u8> {
     unsafe {
         let len = encodeStateLen(s) as usize;
-        let mut buf = Vec::<i8>::with_capacity(len);
+        let mut buf = Vec::<u8>::with_capacity(len);
         buf.set_len(len);
-        encodeState(s, buf.as_mut_ptr(), buf.len() as c_int);
+        encodeState(s, buf.as_mut_ptr() as *mut i8, buf.len() as c_int);
 
         buf
     }
 }
 
-fn decode_state(serialized: &Vec<i8>) -> *mut CAleState {
-    unsafe {
-        decodeState(serialized.as_ptr(), serialized.len() as c_int)
+// States were always encoded as signed bytes, keep doing so for old files.
+#[cfg(feature="rustc-serialize")]
+fn as_signed(serialized: &[u8]) -> &[i8] {
+    unsafe { ::std::slice::from_raw_parts(serialized.as_ptr() as *const i8, serialized.len()) }
+}
+
+/// Frames a state as
+///
+/// ```text
+/// byte 0      b'A'
+/// byte 1      format version, currently 3
+/// byte 2      0 for an AleState, 1 for an AleSystemState
+/// byte 3      1 if the state is tagged with a ROM, otherwise 0
+/// bytes 4..20 the MD5 of the ROM, only present if tagged
+/// next 4      length n of the serialized state, u32 little-endian
+/// next n      the serialized state
+/// next 8      FNV-1a hash of all the bytes above, u64 little-endian
+/// ```
+fn frame_state(kind: u8, rom_md5: Option<[u8; 16]>, serialized: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(serialized.len() + 32);
+    frame.extend_from_slice(&[FORMAT_MAGIC, FORMAT_VERSION, kind]);
+    match rom_md5 {
+        Some(md5) => {
+            frame.push(1);
+            frame.extend_from_slice(&md5);
+        },
+        None => frame.push(0),
+    }
+
+    let len = serialized.len() as u32;
+    frame.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+    frame.extend_from_slice(serialized);
+
+    let hash = fnv1a(&frame);
+    for i in 0..8 {
+        frame.push((hash >> (8 * i)) as u8);
+    }
+
+    frame
+}
+
+fn write_state<W: Write>(w: &mut W, kind: u8, rom_md5: Option<[u8; 16]>, serialized: &[u8]) -> io::Result<()> {
+    w.write_all(&frame_state(kind, rom_md5, serialized))
+}
+
+/// Reads a state framed by `frame_state`, checking everything that can be
+/// checked before the serialized state is handed to ALE.
+fn read_state<R: Read>(r: &mut R, kind: u8) -> Result<(Option<[u8; 16]>, Vec<u8>), Error> {
+    // The frame is collected as it is read, to check its hash at the end.
+    let mut frame = vec![0u8; 3];
+    try!(r.read_exact(&mut frame));
+
+    if frame[0] != FORMAT_MAGIC {
+        return Err(Error::StateDecode("not a serialized ALE state"));
+    }
+    if frame[1] != FORMAT_VERSION {
+        return Err(Error::StateDecode("unsupported state format version"));
+    }
+    if frame[2] != kind {
+        return Err(Error::StateDecode("expected a state but found a system state, or vice versa"));
+    }
+
+    let mut rom_md5 = None;
+    let mut tagged = [0u8; 1];
+    try!(r.read_exact(&mut tagged));
+    frame.push(tagged[0]);
+    match tagged[0] {
+        0 => {},
+        1 => {
+            let mut md5 = [0u8; 16];
+            try!(r.read_exact(&mut md5));
+            frame.extend_from_slice(&md5);
+            rom_md5 = Some(md5);
+        },
+        _ => return Err(Error::StateDecode("invalid ROM tag")),
+    }
+
+    let mut len = [0u8; 4];
+    try!(r.read_exact(&mut len));
+    frame.extend_from_slice(&len);
+    let len = len[0] as usize | (len[1] as usize) << 8
+            | (len[2] as usize) << 16 | (len[3] as usize) << 24;
+    if len > MAX_STATE_LEN {
+        return Err(Error::StateDecode("the serialized state is too long"));
+    }
+
+    let mut serialized = vec![0u8; len];
+    try!(r.read_exact(&mut serialized));
+    frame.extend_from_slice(&serialized);
+
+    let mut hash = [0u8; 8];
+    try!(r.read_exact(&mut hash));
+    let hash = hash.iter().rev().fold(0u64, |h, &b| (h << 8) | b as u64);
+    if hash != fnv1a(&frame) {
+        return Err(Error::StateDecode("the state's hash does not match, it is corrupt"));
+    }
+
+    Ok((rom_md5, serialized))
+}
+
+/// Reads a state framed by `frame_state` that must fill all of `bytes`.
+fn parse_state(bytes: &[u8], kind: u8) -> Result<(Option<[u8; 16]>, Vec<u8>), Error> {
+    let mut r = bytes;
+    let parsed = try!(read_state(&mut r, kind));
+    if !r.is_empty() {
+        return Err(Error::StateDecode("unexpected bytes after the serialized state"));
+    }
+
+    Ok(parsed)
+}
+
+/// Hands a serialized state to ALE. ALE doesn't validate it, so it must come
+/// from a frame checked by `read_state`, from ALE itself or from a legacy
+/// save game.
+fn decode_state(serialized: &[u8]) -> Result<*mut CAleState, Error> {
+    if serialized.is_empty() {
+        return Err(Error::StateDecode("the serialized state is empty"));
+    }
+    if serialized.len() > MAX_STATE_LEN {
+        return Err(Error::StateDecode("the serialized state is too long"));
+    }
+
+    let s = unsafe {
+        decodeState(serialized.as_ptr() as *const i8, serialized.len() as c_int)
+    };
+
+    if s.is_null() {
+        Err(Error::StateDecode("ALE returned a null state"))
+    } else {
+        Ok(s)
+    }
+}
+
+#[cfg(feature="serde")]
+mod serde_bytes {
+    use std::fmt;
+    use ::serde::de::{Error,SeqAccess,Visitor};
+
+    /// Accepts both native byte strings and sequences of bytes, for formats
+    /// like JSON without a byte string type.
+    pub struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a serialized ALE state")
+        }
+
+        fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = try!(seq.next_element()) {
+                bytes.push(byte);
+            }
+
+            Ok(bytes)
+        }
     }
 }
 
@@ -84,7 +481,7 @@ pub mod protected {
     pub trait Protected {
         #[inline]
         fn s(&self) -> *mut CAleState;
-        fn new(s: *mut CAleState) -> Self;
+        fn new(s: *mut CAleState, rom_md5: Option<[u8; 16]>) -> Self;
     }
 
     impl Protected for AleState {
@@ -92,8 +489,8 @@ pub mod protected {
             self.s
         }
 
-        fn new(s: *mut CAleState) -> AleState {
-            AleState{s: s}
+        fn new(s: *mut CAleState, rom_md5: Option<[u8; 16]>) -> AleState {
+            AleState{s: s, rom_md5: rom_md5}
         }
     }
 
@@ -102,8 +499,101 @@ pub mod protected {
             self.s
         }
 
-        fn new(s: *mut CAleState) -> AleSystemState {
-            AleSystemState{s: s}
+        fn new(s: *mut CAleState, rom_md5: Option<[u8; 16]>) -> AleSystemState {
+            AleSystemState{s: s, rom_md5: rom_md5}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::error::Error;
+    use super::*;
+
+    const MD5: [u8; 16] = [7; 16];
+
+    fn payload() -> Vec<u8> {
+        (0..200).map(|i| (i * 31) as u8).collect()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        for &rom_md5 in &[None, Some(MD5)] {
+            let frame = frame_state(SYSTEM_STATE_KIND, rom_md5, &payload());
+            let (md5, serialized) = parse_state(&frame, SYSTEM_STATE_KIND).unwrap();
+
+            assert_eq!(md5, rom_md5);
+            assert_eq!(serialized, payload());
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        for &version in &[0, 1, 2, FORMAT_VERSION + 1] {
+            let mut frame = frame_state(STATE_KIND, None, &payload());
+            frame[1] = version;
+
+            match parse_state(&frame, STATE_KIND) {
+                Err(Error::StateDecode(_)) => {},
+                other => panic!("expected a state decode error for version {}, got {:?}", version, other),
+            }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn empty_input_is_rejected() {
+        assert!(AleState::from_bytes(&[]).is_err());
+        assert!(AleSystemState::from_bytes(&[]).is_err());
+        assert!(AleState::read_from(&mut &[][..]).is_err());
+        assert!(decode_state(&[]).is_err());
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let frame = frame_state(STATE_KIND, Some(MD5), &payload());
+
+        for len in 0..frame.len() {
+            assert!(parse_state(&frame[..len], STATE_KIND).is_err(), "accepted a frame cut to {} bytes", len);
+            assert!(AleState::from_bytes(&frame[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn bit_flips_are_rejected() {
+        let frame = frame_state(STATE_KIND, Some(MD5), &payload());
+
+        for bit in 0..frame.len() * 8 {
+            let mut corrupt = frame.clone();
+            corrupt[bit / 8] ^= 1 << (bit % 8);
+
+            assert!(parse_state(&corrupt, STATE_KIND).is_err(), "accepted a frame with bit {} flipped", bit);
+            assert!(AleState::from_bytes(&corrupt).is_err());
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let mut frame = frame_state(STATE_KIND, None, &payload());
+        frame.push(0);
+
+        assert!(parse_state(&frame, STATE_KIND).is_err());
+    }
+
+    #[test]
+    fn kinds_are_not_mixed_up() {
+        let frame = frame_state(STATE_KIND, None, &payload());
+
+        assert!(parse_state(&frame, SYSTEM_STATE_KIND).is_err());
+        assert!(AleSystemState::from_bytes(&frame).is_err());
+    }
+
+    #[test]
+    fn huge_lengths_are_rejected_before_allocating() {
+        let frame = [FORMAT_MAGIC, FORMAT_VERSION, STATE_KIND, 0, 0xFF, 0xFF, 0xFF, 0xFF];
+
+        match read_state(&mut &frame[..], STATE_KIND) {
+            Err(Error::StateDecode(_)) => {},
+            other => panic!("expected a state decode error, got {:?}", other),
+        }
+    }
+}
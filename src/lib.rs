@@ -1,23 +1,104 @@
 #![cfg_attr(feature="use_clippy", feature(plugin))]
 #![cfg_attr(feature="use_clippy", plugin(clippy))]
+#[cfg(feature="rustc-serialize")]
 extern crate rustc_serialize;
+#[cfg(feature="serde")]
+#[macro_use]
+extern crate serde;
 extern crate libc;
+extern crate md5;
+
+// The built-in RustcEncodable and RustcDecodable derives are gone from
+// current compilers, so this writes out the impls they generated: a struct
+// of the named fields, encoded in order.
+#[cfg(feature="rustc-serialize")]
+macro_rules! rustc_serialize_struct {
+    ($name:ident { $($field:ident),* }) => {
+        impl ::rustc_serialize::Encodable for $name {
+            fn encode<S: ::rustc_serialize::Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+                let len = [$(stringify!($field)),*].len();
+                s.emit_struct(stringify!($name), len, |s| {
+                    let mut i = 0usize;
+                    $(try!(s.emit_struct_field(stringify!($field), { i += 1; i - 1 }, |s| self.$field.encode(s)));)*
+                    Ok(())
+                })
+            }
+        }
+
+        impl ::rustc_serialize::Decodable for $name {
+            fn decode<D: ::rustc_serialize::Decoder>(d: &mut D) -> Result<Self, D::Error> {
+                let len = [$(stringify!($field)),*].len();
+                d.read_struct(stringify!($name), len, |d| {
+                    let mut i = 0usize;
+                    Ok($name {
+                        $($field: try!(d.read_struct_field(stringify!($field), { i += 1; i - 1 }, ::rustc_serialize::Decodable::decode)),)*
+                    })
+                })
+            }
+        }
+    };
+}
 
 pub mod ffi;
+#[cfg(feature="rustc-serialize")]
+pub mod binary;
+mod bytes;
+pub mod compact;
+mod error;
 mod game;
+pub mod noop;
 pub mod serialize;
-pub use self::game::{Game,AleState,AleSystemState};
+#[cfg(feature="rustc-serialize")]
+pub mod migrate;
+pub mod npy;
+pub mod record;
+pub mod registry;
+pub mod replay;
+pub mod replay_buffer;
+pub mod rom_cache;
+pub mod seed;
+pub mod settings;
+pub mod sticky;
+pub mod terminal;
+pub mod video;
+pub use self::compact::{CompactRecorder,CompactReplay};
+pub use self::error::Error;
+pub use self::game::{Env,Game,AleState,AleSystemState,Checkpoints,StepInfo};
+pub use self::record::{Recorder,RecordingReader};
+pub use self::registry::RomRegistry;
+pub use self::replay::Replayer;
+pub use self::replay_buffer::ReplayBuffer;
+pub use self::rom_cache::RomCache;
+pub use self::settings::{Settings,SettingChange};
+pub use self::sticky::StickyActions;
 
 use ::ffi::*;
 use ::libc::c_int;
 use ::game::protected::Protected;
+use ::seed::EpisodeSeed;
 use std::ffi::{CStr,CString};
 use std::ops::Drop;
 use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT};
 
-#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, RustcEncodable, RustcDecodable)]
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
 pub struct Action(pub i32);
 
+// Encoded as the derive encoded tuple structs, with a field named _field0.
+#[cfg(feature="rustc-serialize")]
+impl ::rustc_serialize::Encodable for Action {
+    fn encode<S: ::rustc_serialize::Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_struct("Action", 1, |s| s.emit_struct_field("_field0", 0, |s| s.emit_i32(self.0)))
+    }
+}
+
+#[cfg(feature="rustc-serialize")]
+impl ::rustc_serialize::Decodable for Action {
+    fn decode<D: ::rustc_serialize::Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        d.read_struct("Action", 1, |d| d.read_struct_field("_field0", 0, |d| d.read_i32()).map(Action))
+    }
+}
+
 pub struct Ale {
     p: *mut AleInterface
 }
@@ -60,6 +141,11 @@ impl Ale {
         }
     }
 
+    /// The underlying ALE, for calling `ffi` functions directly.
+    pub fn as_raw_ptr(&self) -> *mut AleInterface {
+        self.p
+    }
+
     pub fn get_bool(&self, key: &str) -> bool {
         unsafe {
             let key = CString::new(key).unwrap();
@@ -127,6 +213,29 @@ impl Ale {
         Game::new(self, file_name.to_owned())
     }
 
+    /// load_rom_seeded loads a rom like load_rom, but first sets ALE's
+    /// random_seed from episode 0 of environment `env_id` under
+    /// `master_seed`. Each Game::reset then moves on to the next episode
+    /// seed, and the seed in use is reported by Game::step, so any episode
+    /// can be reproduced exactly. See the seed module for the derivation.
+    pub fn load_rom_seeded(self, file_name: &str, master_seed: u64, env_id: u64) -> Game {
+        self.load_rom_with_seed(file_name, EpisodeSeed::new(master_seed, env_id, 0))
+    }
+
+    /// load_rom_with_seed is like load_rom_seeded, but starts at the given
+    /// episode seed, e.g. one reported by Game::step in an earlier run.
+    pub fn load_rom_with_seed(mut self, file_name: &str, seed: EpisodeSeed) -> Game {
+        self.set_int("random_seed", ::seed::ale_seed(seed.seed));
+
+        unsafe {
+            let file_name = CString::new(file_name).unwrap();
+
+            loadROM(self.p, file_name.as_ptr());
+        }
+
+        Game::new_seeded(self, file_name.to_owned(), seed)
+    }
+
 }
 
 impl Drop for Ale {
@@ -0,0 +1,339 @@
+//! Converting save games written before the versioned save game format.
+//!
+//! Both JSON (`rustc_serialize::json`) and binary (`ale::binary`) files are
+//! supported. Legacy files are restored with `LegacyGameDecoder` and written
+//! again with the current `Game` encoder, in the same encoding, then both
+//! versions are restored once more, played for `VERIFY_FRAMES` frames with
+//! the same actions, and their RAM and screens compared after every frame.
+//!
+//! Restoring a game writes its ROM to disk, so every function that does
+//! takes the `RomCache` to write it to.
+use std::fs::{self,File};
+use std::io::{Read,Write};
+use std::path::{Path,PathBuf};
+use std::str;
+use rustc_serialize::json::{self,Json};
+use ::{binary,Error,Game,RomCache};
+use ::serialize::{GameDecoder,LegacyGameDecoder,SAVE_MAGIC};
+
+/// How many frames `verify` plays on both versions of a game.
+pub const VERIFY_FRAMES: usize = 60;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Encoding {
+    Json,
+    Binary,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Layout {
+    /// ROM path, ROM data and system state back to back, as read by
+    /// `LegacyGameDecoder`.
+    Legacy,
+    /// The versioned container read by `GameDecoder`.
+    Current,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Outcome {
+    /// The file was already in the current format and was left alone.
+    AlreadyCurrent,
+    /// The file was converted and the conversion verified.
+    Migrated,
+}
+
+/// The result of migrating one file.
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub result: Result<Outcome, Error>,
+}
+
+/// Works out how a save game was encoded without restoring it.
+pub fn detect(bytes: &[u8]) -> Result<(Encoding, Layout), Error> {
+    if let Ok(text) = str::from_utf8(bytes) {
+        let text = text.trim_start();
+        if text.starts_with('{') {
+            let is_current = match Json::from_str(text) {
+                Ok(Json::Object(ref fields)) => fields.contains_key("header"),
+                _ => false,
+            };
+            if is_current {
+                return Ok((Encoding::Json, Layout::Current));
+            }
+        } else if text.starts_with('"') {
+            return Ok((Encoding::Json, Layout::Legacy));
+        }
+    }
+
+    // Both binary layouts start with a string: the header magic in the
+    // current one, the ROM path in the legacy one.
+    match binary::decode::<String>(bytes) {
+        Ok(ref magic) if magic == SAVE_MAGIC => Ok((Encoding::Binary, Layout::Current)),
+        Ok(_) => Ok((Encoding::Binary, Layout::Legacy)),
+        Err(_) => Err(Error::Format("not a JSON or binary ALE save game".to_owned())),
+    }
+}
+
+fn decode_legacy(bytes: &[u8], encoding: Encoding) -> Result<LegacyGameDecoder, Error> {
+    match encoding {
+        Encoding::Binary => binary::decode(bytes),
+        Encoding::Json => {
+            let text = try!(str::from_utf8(bytes).map_err(|_| Error::Format("invalid UTF-8".to_owned())));
+            let values = try!(split_json_values(text));
+            if values.len() != 3 {
+                return Err(Error::Format(format!("expected 3 JSON values in a legacy save game, found {}", values.len())));
+            }
+
+            let rom_path: String = try!(json::decode(values[0]).map_err(json_error));
+            let romfile = try!(json::decode(values[1]).map_err(json_error));
+            let initial_state = try!(json::decode(values[2]).map_err(json_error));
+
+            Ok(LegacyGameDecoder::from_parts(PathBuf::from(rom_path), romfile, initial_state))
+        },
+    }
+}
+
+fn decode_current(bytes: &[u8], encoding: Encoding) -> Result<GameDecoder, Error> {
+    match encoding {
+        Encoding::Binary => binary::decode(bytes),
+        Encoding::Json => {
+            let text = try!(str::from_utf8(bytes).map_err(|_| Error::Format("invalid UTF-8".to_owned())));
+            json::decode(text).map_err(json_error)
+        },
+    }
+}
+
+fn encode_game(game: &Game, encoding: Encoding) -> Result<Vec<u8>, Error> {
+    match encoding {
+        Encoding::Binary => binary::encode(game),
+        Encoding::Json => json::encode(game).map(String::into_bytes)
+                              .map_err(|err| Error::Format(err.to_string())),
+    }
+}
+
+fn json_error<E: ::std::fmt::Display>(err: E) -> Error {
+    Error::Format(err.to_string())
+}
+
+/// Splits text holding JSON values written back to back into the text of
+/// each value. Only the boundaries are checked, not the values themselves.
+fn split_json_values(text: &str) -> Result<Vec<&str>, Error> {
+    let bytes = text.as_bytes();
+    let mut values = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while i < bytes.len() && (bytes[i] as char).is_whitespace() {
+            i += 1;
+        }
+        if i == bytes.len() {
+            return Ok(values);
+        }
+
+        let start = i;
+        let mut depth = 0usize;
+        let mut in_string = false;
+        while i < bytes.len() {
+            let c = bytes[i];
+            i += 1;
+
+            if in_string {
+                match c {
+                    b'\\' => i += 1,
+                    b'"' => in_string = false,
+                    _ => {},
+                }
+            } else {
+                match c {
+                    b'"' => in_string = true,
+                    b'[' | b'{' => depth += 1,
+                    b']' | b'}' => {
+                        if depth == 0 {
+                            return Err(Error::Format("unbalanced JSON".to_owned()));
+                        }
+                        depth -= 1;
+                    },
+                    _ => {},
+                }
+            }
+
+            // Scalars end at the next delimiter, compound values and strings
+            // when they close.
+            let at_delimiter = i == bytes.len() || match bytes[i] {
+                b'"' | b'[' | b'{' => true,
+                c => (c as char).is_whitespace(),
+            };
+            if !in_string && depth == 0 && (c == b'"' || c == b']' || c == b'}' || at_delimiter) {
+                break;
+            }
+        }
+
+        if in_string || depth != 0 {
+            return Err(Error::Format("truncated JSON".to_owned()));
+        }
+        values.push(&text[start..i]);
+    }
+}
+
+/// Converts a save game to the current layout, in the same encoding.
+/// Returns `None` if it already is in the current layout.
+pub fn convert(bytes: &[u8], cache: &RomCache) -> Result<Option<Vec<u8>>, Error> {
+    let (encoding, layout) = try!(detect(bytes));
+    if layout == Layout::Current {
+        return Ok(None);
+    }
+
+    let game = try!(try!(decode_legacy(bytes, encoding)).try_decode_game_in(cache));
+    encode_game(&game, encoding).map(Some)
+}
+
+/// Restores a legacy save game and its conversion in turn, plays both with
+/// the same actions and checks that their RAM and screens match on every
+/// frame.
+pub fn verify(legacy: &[u8], converted: &[u8], cache: &RomCache) -> Result<(), Error> {
+    let (encoding, _) = try!(detect(legacy));
+
+    // Only one ALE may exist at a time, so each game is dropped before the
+    // next one is decoded.
+    let expected = {
+        let mut game = try!(try!(decode_legacy(legacy, encoding)).try_decode_game_in(cache));
+        trace(&mut game)
+    };
+    let actual = {
+        let mut game = try!(try!(decode_current(converted, encoding)).try_decode_game_in(cache));
+        trace(&mut game)
+    };
+
+    for (frame, ((ram, screen), (legacy_ram, legacy_screen))) in actual.iter().zip(&expected).enumerate() {
+        if ram != legacy_ram {
+            return Err(Error::Format(format!("the migrated game's RAM differs from the original at frame {}", frame)));
+        }
+        if screen != legacy_screen {
+            return Err(Error::Format(format!("the migrated game's screen differs from the original at frame {}", frame)));
+        }
+    }
+
+    Ok(())
+}
+
+/// The RAM and screen of a game as restored and after each of
+/// `VERIFY_FRAMES` frames, cycling through its minimal action set.
+fn trace(game: &mut Game) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let actions = game.minimal_action_set();
+    let mut frames = Vec::with_capacity(VERIFY_FRAMES + 1);
+    frames.push((game.ram(), game.screen()));
+
+    for frame in 0..VERIFY_FRAMES {
+        game.act(actions[frame % actions.len()]);
+        frames.push((game.ram(), game.screen()));
+    }
+
+    frames
+}
+
+/// Migrates the file at `src`, writing the result to `dst`, which may be
+/// the same path. Nothing is written unless the conversion verifies. Files
+/// already in the current format are copied to `dst` unchanged. The ROM is
+/// written to `cache`.
+pub fn migrate_file(src: &Path, dst: &Path, cache: &RomCache) -> Result<Outcome, Error> {
+    let mut bytes = Vec::new();
+    try!(try!(File::open(src)).read_to_end(&mut bytes));
+
+    let (outcome, output) = match try!(convert(&bytes, cache)) {
+        Some(converted) => {
+            try!(verify(&bytes, &converted, cache));
+            (Outcome::Migrated, converted)
+        },
+        None => (Outcome::AlreadyCurrent, bytes),
+    };
+
+    if src == dst && outcome == Outcome::AlreadyCurrent {
+        return Ok(outcome);
+    }
+
+    let mut temp_name = dst.file_name().expect("destination has no file name").to_os_string();
+    temp_name.push(".migrating");
+    let temp_path = dst.with_file_name(temp_name);
+    {
+        let mut file = try!(File::create(&temp_path));
+        try!(file.write_all(&output));
+    }
+    try!(fs::rename(&temp_path, dst));
+
+    Ok(outcome)
+}
+
+/// Migrates each file in place, or into `out_dir` if given, and reports
+/// the outcome per file. A failure doesn't stop the remaining files.
+pub fn migrate_files<P: AsRef<Path>>(paths: &[P], out_dir: Option<&Path>, cache: &RomCache) -> Vec<FileReport> {
+    paths.iter().map(|path| {
+        let src = path.as_ref();
+        let dst = match out_dir {
+            Some(dir) => dir.join(src.file_name().expect("save game path has no file name")),
+            None => src.to_path_buf(),
+        };
+
+        FileReport {
+            path: src.to_path_buf(),
+            result: migrate_file(src, &dst, cache),
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ::binary;
+    use ::serialize::SAVE_MAGIC;
+    use super::*;
+
+    #[test]
+    fn detect_tells_the_layouts_apart() {
+        assert_eq!(detect(b"\"roms/pong.bin\"[1,2]{}").unwrap(), (Encoding::Json, Layout::Legacy));
+        assert_eq!(detect(b"  \n\"roms/pong.bin\"").unwrap(), (Encoding::Json, Layout::Legacy));
+        assert_eq!(detect(b"{\"header\":{\"magic\":\"x\"},\"rom\":[]}").unwrap(), (Encoding::Json, Layout::Current));
+
+        let mut current = binary::encode(&SAVE_MAGIC.to_owned()).unwrap();
+        current.extend_from_slice(&[0, 0, 0, 4]);
+        assert_eq!(detect(&current).unwrap(), (Encoding::Binary, Layout::Current));
+
+        let mut legacy = binary::encode(&"roms/pong.bin".to_owned()).unwrap();
+        legacy.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(detect(&legacy).unwrap(), (Encoding::Binary, Layout::Legacy));
+    }
+
+    #[test]
+    fn detect_rejects_other_data() {
+        assert!(detect(b"").is_err());
+        assert!(detect(b"{\"rom\":[]}").is_err());
+        assert!(detect(&[0xFF; 12]).is_err());
+        // A string length that runs past the end of the input.
+        assert!(detect(&[0, 0, 0, 0, 0, 0, 0, 9, b'r']).is_err());
+    }
+
+    #[test]
+    fn splits_back_to_back_values() {
+        assert_eq!(split_json_values("").unwrap(), Vec::<&str>::new());
+        assert_eq!(split_json_values(" \n ").unwrap(), Vec::<&str>::new());
+        assert_eq!(split_json_values("12 true null -1.5e3").unwrap(), vec!["12", "true", "null", "-1.5e3"]);
+        assert_eq!(split_json_values("\"rom\"[1,2]{\"x\":1}").unwrap(), vec!["\"rom\"", "[1,2]", "{\"x\":1}"]);
+        assert_eq!(split_json_values("1\"a\"2").unwrap(), vec!["1", "\"a\"", "2"]);
+    }
+
+    #[test]
+    fn strings_can_hold_delimiters() {
+        assert_eq!(split_json_values(r#""a\"b" {"k": ["}", "\\"]} "[""#).unwrap(),
+                   vec![r#""a\"b""#, r#"{"k": ["}", "\\"]}"#, r#""[""#]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_unbalanced_values() {
+        assert!(split_json_values("{\"a\": 1").is_err());
+        assert!(split_json_values("[1, [2]").is_err());
+        assert!(split_json_values("\"abc").is_err());
+        assert!(split_json_values("\"abc\\\"").is_err());
+        assert!(split_json_values("]").is_err());
+        assert!(split_json_values("[1]]").is_err());
+        assert!(split_json_values("{\"a\": 1}}").is_err());
+    }
+}
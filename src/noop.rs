@@ -0,0 +1,71 @@
+//! Seeded no-op starts.
+//!
+//! Starting each episode with a random number of no-op actions is a common
+//! way to vary initial states in games that always start the same way. The
+//! number is drawn from the no-op starts stream of the episode seed (see the
+//! `seed` module), so it is reproduced by the seed alone and doesn't consume
+//! the RNG of ALE or of any other wrapper.
+use ::{Action,Env};
+use ::seed::{self,EpisodeSeed,Rng};
+
+/// The action ALE treats as doing nothing.
+pub const NOOP: Action = Action(0);
+
+/// The number of no-op actions to start an episode with, drawn uniformly
+/// from `0..=max`.
+pub fn noop_count(seed: &EpisodeSeed, max: u32) -> u32 {
+    Rng::new(seed.stream(seed::NOOP_STARTS_STREAM)).below(max as u64 + 1) as u32
+}
+
+/// Performs the no-op starts of the current episode, stopping early if the
+/// game ends, and returns how many were performed. Call it after loading the
+/// game and after every reset.
+///
+/// The no-ops go through `env`, so wrappers such as `StickyActions` see them.
+/// To record them, step the `Recorder` `noop_count` times instead.
+///
+/// Panics if the game was not loaded with `Ale::load_rom_seeded`.
+pub fn noop_start<E: Env>(env: &mut E, max: u32) -> u32 {
+    let seed = env.game().seed().expect("noop_start needs a game loaded with Ale::load_rom_seeded");
+
+    let count = noop_count(&seed, max);
+    for performed in 0..count {
+        if env.step(NOOP).terminal {
+            return performed + 1;
+        }
+    }
+
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use ::seed::EpisodeSeed;
+    use super::*;
+
+    #[test]
+    fn counts_are_in_range_and_reproducible() {
+        for episode in 0..200 {
+            let seed = EpisodeSeed::new(7, 3, episode);
+            let count = noop_count(&seed, 30);
+
+            assert!(count <= 30);
+            assert_eq!(count, noop_count(&EpisodeSeed::new(7, 3, episode), 30));
+        }
+    }
+
+    #[test]
+    fn counts_cover_the_range() {
+        let mut seen = [false; 4];
+        for episode in 0..200 {
+            seen[noop_count(&EpisodeSeed::new(1, 0, episode), 3) as usize] = true;
+        }
+
+        assert_eq!(seen, [true; 4]);
+    }
+
+    #[test]
+    fn zero_max_means_no_noops() {
+        assert_eq!(noop_count(&EpisodeSeed::new(1, 2, 3), 0), 0);
+    }
+}
@@ -0,0 +1,541 @@
+//! Exporting transitions as NumPy `.npy` and `.npz` files.
+//!
+//! `.npy` files are written in format version 1.0: a small header describing
+//! the element type and shape of the array, followed by the elements in
+//! little-endian C order. `.npz` bundles are uncompressed zip files of
+//! `.npy` entries, which `numpy.load` opens like a dict of arrays.
+//!
+//! An `Exporter` collects transitions from a `Game` in chunks, writing each
+//! chunk to its own file once it's full, so a dataset never has to fit in
+//! memory at once. In Python a dataset can be loaded with e.g.
+//!
+//! ```text
+//! chunks = [np.load(path) for path in sorted(glob.glob("breakout-*.npz"))]
+//! observations = np.concatenate([c["observations"] for c in chunks])
+//! ```
+use std::fs::File;
+use std::io::{self,BufWriter,Write};
+use std::path::{Path,PathBuf};
+use ::{Action,Error,Game,StepInfo};
+use ::bytes::*;
+
+/// A type that can be stored in a `.npy` array.
+pub trait Element: Copy {
+    /// The NumPy type string, e.g. `<i4`.
+    fn descr() -> &'static str;
+    fn extend_le(values: &[Self], out: &mut Vec<u8>);
+}
+
+impl Element for u8 {
+    fn descr() -> &'static str { "|u1" }
+
+    fn extend_le(values: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(values);
+    }
+}
+
+impl Element for bool {
+    fn descr() -> &'static str { "|b1" }
+
+    fn extend_le(values: &[bool], out: &mut Vec<u8>) {
+        out.extend(values.iter().map(|&v| v as u8));
+    }
+}
+
+impl Element for i32 {
+    fn descr() -> &'static str { "<i4" }
+
+    fn extend_le(values: &[i32], out: &mut Vec<u8>) {
+        for &v in values {
+            let _ = write_i32(out, v);
+        }
+    }
+}
+
+impl Element for u32 {
+    fn descr() -> &'static str { "<u4" }
+
+    fn extend_le(values: &[u32], out: &mut Vec<u8>) {
+        for &v in values {
+            let _ = write_u32(out, v);
+        }
+    }
+}
+
+impl Element for u64 {
+    fn descr() -> &'static str { "<u8" }
+
+    fn extend_le(values: &[u64], out: &mut Vec<u8>) {
+        for &v in values {
+            let _ = write_u64(out, v);
+        }
+    }
+}
+
+impl Element for f32 {
+    fn descr() -> &'static str { "<f4" }
+
+    fn extend_le(values: &[f32], out: &mut Vec<u8>) {
+        for &v in values {
+            let _ = write_f32(out, v);
+        }
+    }
+}
+
+impl Element for f64 {
+    fn descr() -> &'static str { "<f8" }
+
+    fn extend_le(values: &[f64], out: &mut Vec<u8>) {
+        for &v in values {
+            let _ = write_f64(out, v);
+        }
+    }
+}
+
+/// Encodes an array as a `.npy` file. Panics if `data` doesn't have as
+/// many elements as `shape` describes.
+pub fn npy_bytes<T: Element>(shape: &[usize], data: &[T]) -> Vec<u8> {
+    assert_eq!(shape.iter().product::<usize>(), data.len(), "npy data doesn't match its shape");
+
+    let shape = match shape.len() {
+        1 => format!("({},)", shape[0]),
+        _ => format!("({})", shape.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", T::descr(), shape);
+
+    // The magic, version and header length take 10 bytes, and the data
+    // must start at a multiple of 64.
+    let padding = 63 - (10 + header.len()) % 64;
+    for _ in 0..padding {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let mut out = Vec::with_capacity(10 + header.len() + data.len());
+    out.extend_from_slice(b"\x93NUMPY\x01\x00");
+    let _ = write_u16(&mut out, header.len() as u16);
+    out.extend_from_slice(header.as_bytes());
+    T::extend_le(data, &mut out);
+
+    out
+}
+
+pub fn write_npy<W: Write, T: Element>(w: &mut W, shape: &[usize], data: &[T]) -> io::Result<()> {
+    w.write_all(&npy_bytes(shape, data))
+}
+
+struct ZipEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes a `.npz` file, an uncompressed zip of `.npy` entries. Entries are
+/// limited to 4 GiB each, split larger datasets into chunks.
+pub struct NpzWriter<W: Write> {
+    w: W,
+    crc: Crc32,
+    offset: u64,
+    entries: Vec<ZipEntry>,
+}
+
+// 1980-01-01, the earliest date zip can store.
+const ZIP_DATE: u16 = (1 << 5) | 1;
+
+impl<W: Write> NpzWriter<W> {
+    pub fn new(w: W) -> Self {
+        NpzWriter {
+            w: w,
+            crc: Crc32::new(),
+            offset: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds an array, stored as `<name>.npy`.
+    pub fn add<T: Element>(&mut self, name: &str, shape: &[usize], data: &[T]) -> Result<(), Error> {
+        let npy = npy_bytes(shape, data);
+        self.add_file(&format!("{}.npy", name), &npy)
+    }
+
+    fn add_file(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        if data.len() as u64 > u32::max_value() as u64 || self.offset > u32::max_value() as u64 {
+            return Err(Error::Format("npz files are limited to 4 GiB, use smaller chunks".to_owned()));
+        }
+        if self.entries.len() == u16::max_value() as usize {
+            return Err(Error::Format("too many arrays for an npz file".to_owned()));
+        }
+
+        self.crc.reset();
+        self.crc.update(data);
+        let entry = ZipEntry {
+            name: name.to_owned(),
+            crc: self.crc.finish(),
+            size: data.len() as u32,
+            offset: self.offset as u32,
+        };
+
+        let w = &mut self.w;
+        try!(write_u32(w, 0x04034b50));
+        try!(write_u16(w, 20));
+        try!(write_u16(w, 0));
+        try!(write_u16(w, 0));
+        try!(write_u16(w, 0));
+        try!(write_u16(w, ZIP_DATE));
+        try!(write_u32(w, entry.crc));
+        try!(write_u32(w, entry.size));
+        try!(write_u32(w, entry.size));
+        try!(write_u16(w, name.len() as u16));
+        try!(write_u16(w, 0));
+        try!(w.write_all(name.as_bytes()));
+        try!(w.write_all(data));
+
+        self.offset += 30 + name.len() as u64 + data.len() as u64;
+        self.entries.push(entry);
+
+        Ok(())
+    }
+
+    /// Writes the zip directory and returns the writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.offset > u32::max_value() as u64 {
+            return Err(Error::Format("npz files are limited to 4 GiB, use smaller chunks".to_owned()));
+        }
+
+        let directory_offset = self.offset as u32;
+        let mut directory_size = 0u32;
+        {
+            let w = &mut self.w;
+            for entry in &self.entries {
+                try!(write_u32(w, 0x02014b50));
+                try!(write_u16(w, 20));
+                try!(write_u16(w, 20));
+                try!(write_u16(w, 0));
+                try!(write_u16(w, 0));
+                try!(write_u16(w, 0));
+                try!(write_u16(w, ZIP_DATE));
+                try!(write_u32(w, entry.crc));
+                try!(write_u32(w, entry.size));
+                try!(write_u32(w, entry.size));
+                try!(write_u16(w, entry.name.len() as u16));
+                try!(write_u16(w, 0));
+                try!(write_u16(w, 0));
+                try!(write_u16(w, 0));
+                try!(write_u16(w, 0));
+                try!(write_u32(w, 0));
+                try!(write_u32(w, entry.offset));
+                try!(w.write_all(entry.name.as_bytes()));
+                directory_size += 46 + entry.name.len() as u32;
+            }
+
+            try!(write_u32(w, 0x06054b50));
+            try!(write_u16(w, 0));
+            try!(write_u16(w, 0));
+            try!(write_u16(w, self.entries.len() as u16));
+            try!(write_u16(w, self.entries.len() as u16));
+            try!(write_u32(w, directory_size));
+            try!(write_u32(w, directory_offset));
+            try!(write_u16(w, 0));
+            try!(w.flush());
+        }
+
+        Ok(self.w)
+    }
+}
+
+/// What to store as the observation of each transition.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Observation {
+    /// No observations, e.g. when only RAM is needed.
+    Nothing,
+    /// The screen as palette indices, shape `(N, height, width)`.
+    Screen,
+    /// The RGB screen, shape `(N, height, width, 3)`.
+    ScreenRgb,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExportOptions {
+    pub observation: Observation,
+    pub ram: bool,
+    /// The number of transitions per file.
+    pub chunk_len: usize,
+    /// Writes one `.npz` file per chunk rather than one `.npy` file per
+    /// array and chunk.
+    pub npz: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            observation: Observation::Screen,
+            ram: true,
+            chunk_len: 100000,
+            npz: true,
+        }
+    }
+}
+
+/// Collects transitions and writes them in chunks to files named
+/// `<prefix>-<chunk>.npz`, or `<prefix>-<chunk>-<array>.npy`. Each chunk
+/// holds the arrays `observations`, `actions`, `rewards`, `terminals` and,
+/// if enabled, `ram`, where row `t` holds the observation and RAM before
+/// action `t` and the reward and end of episode that followed it.
+pub struct Exporter {
+    dir: PathBuf,
+    prefix: String,
+    options: ExportOptions,
+    chunk: usize,
+    files: Vec<PathBuf>,
+    observation_shape: Vec<usize>,
+    ram_size: usize,
+    observations: Vec<u8>,
+    actions: Vec<i32>,
+    rewards: Vec<i32>,
+    terminals: Vec<bool>,
+    ram: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+impl Exporter {
+    /// Creates an exporter for observations of the given shape, e.g. from
+    /// `Exporter::for_game`. Files are written to `dir`, which must exist.
+    pub fn new<P: AsRef<Path>>(dir: P, prefix: &str, options: ExportOptions,
+                               observation_shape: Vec<usize>, ram_size: usize) -> Self {
+        assert!(options.chunk_len > 0, "Exporter chunk_len must be positive");
+
+        Exporter {
+            dir: dir.as_ref().to_path_buf(),
+            prefix: prefix.to_owned(),
+            options: options,
+            chunk: 0,
+            files: Vec::new(),
+            observation_shape: observation_shape,
+            ram_size: ram_size,
+            observations: Vec::new(),
+            actions: Vec::new(),
+            rewards: Vec::new(),
+            terminals: Vec::new(),
+            ram: Vec::new(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Creates an exporter for transitions of the given game.
+    pub fn for_game<P: AsRef<Path>>(dir: P, prefix: &str, options: ExportOptions, game: &Game) -> Self {
+        let (width, height) = game.screen_dimensions();
+        let shape = match options.observation {
+            Observation::Nothing => vec![],
+            Observation::Screen => vec![height as usize, width as usize],
+            Observation::ScreenRgb => vec![height as usize, width as usize, 3],
+        };
+        let ram_size = game.ram_size() as usize;
+
+        Exporter::new(dir, prefix, options, shape, ram_size)
+    }
+
+    fn observation_len(&self) -> usize {
+        match self.options.observation {
+            Observation::Nothing => 0,
+            _ => self.observation_shape.iter().product(),
+        }
+    }
+
+    /// Adds a transition. Writes a chunk if it's full.
+    pub fn push(&mut self, observation: &[u8], ram: &[u8], action: Action, reward: i32, terminal: bool) -> Result<(), Error> {
+        assert_eq!(observation.len(), self.observation_len(), "observation has the wrong length for this Exporter");
+        self.observations.extend_from_slice(observation);
+        if self.options.ram {
+            assert_eq!(ram.len(), self.ram_size, "RAM has the wrong length for this Exporter");
+            self.ram.extend_from_slice(ram);
+        }
+
+        self.push_outcome(action, reward, terminal)
+    }
+
+    fn push_outcome(&mut self, action: Action, reward: i32, terminal: bool) -> Result<(), Error> {
+        self.actions.push(action.0);
+        self.rewards.push(reward);
+        self.terminals.push(terminal);
+
+        if self.actions.len() == self.options.chunk_len {
+            try!(self.flush());
+        }
+
+        Ok(())
+    }
+
+    /// Performs an action in the game and adds the transition.
+    pub fn step(&mut self, game: &mut Game, action: Action) -> Result<StepInfo, Error> {
+        match self.options.observation {
+            Observation::Nothing => {},
+            Observation::Screen => {
+                game.screen_in_buf(&mut self.buf);
+                self.observations.extend_from_slice(&self.buf);
+            },
+            Observation::ScreenRgb => {
+                game.screen_rgb_in_buf(&mut self.buf);
+                self.observations.extend_from_slice(&self.buf);
+            },
+        }
+        if self.options.ram {
+            game.ram_in_buf(&mut self.buf);
+            self.ram.extend_from_slice(&self.buf);
+        }
+
+        let info = game.step(action);
+        try!(self.push_outcome(action, info.reward, info.terminal));
+
+        Ok(info)
+    }
+
+    /// The number of transitions waiting to be written.
+    pub fn pending(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Writes the pending transitions as a chunk, even if it isn't full.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let n = self.actions.len();
+        if n == 0 {
+            return Ok(());
+        }
+
+        let mut observation_shape = vec![n];
+        observation_shape.extend_from_slice(&self.observation_shape);
+        let ram_shape = [n, self.ram_size];
+
+        let name = format!("{}-{:05}", self.prefix, self.chunk);
+        if self.options.npz {
+            let path = self.dir.join(format!("{}.npz", name));
+            let mut npz = NpzWriter::new(BufWriter::new(try!(File::create(&path))));
+            if self.options.observation != Observation::Nothing {
+                try!(npz.add("observations", &observation_shape, &self.observations));
+            }
+            try!(npz.add("actions", &[n], &self.actions));
+            try!(npz.add("rewards", &[n], &self.rewards));
+            try!(npz.add("terminals", &[n], &self.terminals));
+            if self.options.ram {
+                try!(npz.add("ram", &ram_shape, &self.ram));
+            }
+            try!(npz.finish());
+            self.files.push(path);
+        } else {
+            let mut paths = Vec::new();
+            if self.options.observation != Observation::Nothing {
+                paths.push(try!(self.write_array(&name, "observations", &observation_shape, &self.observations)));
+            }
+            paths.push(try!(self.write_array(&name, "actions", &[n], &self.actions)));
+            paths.push(try!(self.write_array(&name, "rewards", &[n], &self.rewards)));
+            paths.push(try!(self.write_array(&name, "terminals", &[n], &self.terminals)));
+            if self.options.ram {
+                paths.push(try!(self.write_array(&name, "ram", &ram_shape, &self.ram)));
+            }
+            self.files.extend(paths);
+        }
+
+        self.observations.clear();
+        self.actions.clear();
+        self.rewards.clear();
+        self.terminals.clear();
+        self.ram.clear();
+        self.chunk += 1;
+
+        Ok(())
+    }
+
+    fn write_array<T: Element>(&self, chunk: &str, array: &str, shape: &[usize], data: &[T]) -> Result<PathBuf, Error> {
+        let path = self.dir.join(format!("{}-{}.npy", chunk, array));
+        let mut w = BufWriter::new(try!(File::create(&path)));
+        try!(write_npy(&mut w, shape, data));
+        try!(w.flush());
+
+        Ok(path)
+    }
+
+    /// Writes the last chunk and returns the paths of all files written.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>, Error> {
+        try!(self.flush());
+
+        Ok(self.files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+    use ::bytes::Crc32;
+    use super::*;
+
+    fn u16_at(bytes: &[u8], at: usize) -> usize {
+        bytes[at] as usize | (bytes[at + 1] as usize) << 8
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        (u16_at(bytes, at) | u16_at(bytes, at + 2) << 16) as u32
+    }
+
+    fn header(npy: &[u8]) -> &str {
+        str::from_utf8(&npy[10..10 + u16_at(npy, 8)]).unwrap()
+    }
+
+    #[test]
+    fn npy_header_is_padded_to_64_bytes() {
+        let npy = npy_bytes(&[2, 3], &[1i32, 2, 3, 4, 5, 6]);
+
+        assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+        let header = header(&npy);
+        assert!(header.starts_with("{'descr': '<i4', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert!(header.ends_with(" \n"));
+        assert_eq!((10 + header.len()) % 64, 0);
+        assert_eq!(npy.len(), 10 + header.len() + 6 * 4);
+        assert_eq!(&npy[10 + header.len()..][..8], &[1, 0, 0, 0, 2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn npy_shapes_use_python_tuples() {
+        assert!(header(&npy_bytes(&[3], &[1u8, 2, 3])).contains("'shape': (3,)"));
+        assert!(header(&npy_bytes(&[0, 4], &[0f32; 0])).contains("'shape': (0, 4)"));
+        assert!(header(&npy_bytes(&[], &[true])).contains("'descr': '|b1', 'fortran_order': False, 'shape': ()"));
+    }
+
+    #[test]
+    fn npy_padding_holds_for_every_header_length() {
+        for len in 0..200 {
+            let shape = [len * 1000 + len];
+            let npy = npy_bytes(&shape, &vec![0u8; shape[0]]);
+            assert_eq!((10 + header(&npy).len()) % 64, 0);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn npy_rejects_data_that_does_not_match_its_shape() {
+        npy_bytes(&[2, 2], &[0u8; 3]);
+    }
+
+    #[test]
+    fn npz_entries_are_stored_zip_files() {
+        let mut npz = NpzWriter::new(Vec::new());
+        npz.add("actions", &[2], &[1i32, 2]).unwrap();
+        let zip = npz.finish().unwrap();
+
+        let npy = npy_bytes(&[2], &[1i32, 2]);
+        let mut crc = Crc32::new();
+        crc.update(&npy);
+
+        assert_eq!(u32_at(&zip, 0), 0x04034b50);
+        assert_eq!(u32_at(&zip, 14), crc.finish());
+        assert_eq!(u32_at(&zip, 18) as usize, npy.len());
+        assert_eq!(u32_at(&zip, 22) as usize, npy.len());
+        assert_eq!(u16_at(&zip, 26), 11);
+        assert_eq!(&zip[30..41], b"actions.npy");
+        assert_eq!(&zip[41..41 + npy.len()], &npy[..]);
+
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(&zip, end), 0x06054b50);
+        assert_eq!(u16_at(&zip, end + 10), 1);
+        assert_eq!(u32_at(&zip, end + 16) as usize, 41 + npy.len());
+    }
+}
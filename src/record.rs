@@ -0,0 +1,549 @@
+//! Recording episodes to a streaming on-disk format.
+//!
+//! A recording is a header followed by a stream of records, all in the
+//! little-endian encoding of the crate's binary formats (`u64` lengths
+//! before byte strings):
+//!
+//! ```text
+//! header   b"ALEREC", format version (u8, currently 1),
+//!          ROM MD5 (u8 flag, then 16 bytes if the flag is 1), ROM path,
+//!          Settings, EpisodeSeed of episode 0 (u8 flag, then master, env id,
+//!          episode and seed as u64s if the flag is 1), record options (u8,
+//!          1 = screen, 2 = RGB screen, 4 = RAM), screen width and height and
+//!          RAM size (i32s), initial AleSystemState (see AleState::write_to)
+//! b'E'     an episode starts: episode index (u64), its EpisodeSeed (as above)
+//! b'S'     a step: action, reward, lives, frame number and episode frame
+//!          number (i32s), flags (u8, 1 = terminal, 2 = sticky), FNV-1a hash
+//!          of the RAM after the step (u64), then the screen, RGB screen and
+//!          RAM as byte strings if enabled in the options
+//! b'Z'     the recording was finished
+//! ```
+//!
+//! Records are written as they happen, so a recording cut short by a crash
+//! is readable up to its last complete record.
+use std::io::{self,Read,Write};
+use std::ops::Deref;
+use ::{Action,AleSystemState,Env,Error,Game,Settings,StepInfo};
+use ::bytes::*;
+use ::seed::EpisodeSeed;
+
+const MAGIC: &'static [u8] = b"ALEREC";
+const FORMAT_VERSION: u8 = 1;
+
+const EPISODE_TAG: u8 = b'E';
+const STEP_TAG: u8 = b'S';
+const END_TAG: u8 = b'Z';
+
+const TERMINAL_FLAG: u8 = 1;
+const STICKY_FLAG: u8 = 2;
+
+/// Upper bound on byte strings when reading, well above any ALE screen.
+const MAX_FIELD_LEN: usize = 1 << 24;
+
+/// Which observations are stored with each step besides the RAM hash.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct RecordOptions {
+    pub screen: bool,
+    pub screen_rgb: bool,
+    pub ram: bool,
+}
+
+impl RecordOptions {
+    fn to_bits(&self) -> u8 {
+        self.screen as u8 | (self.screen_rgb as u8) << 1 | (self.ram as u8) << 2
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        RecordOptions {
+            screen: bits & 1 != 0,
+            screen_rgb: bits & 2 != 0,
+            ram: bits & 4 != 0,
+        }
+    }
+}
+
+pub struct RecordingHeader {
+    pub rom_md5: Option<[u8; 16]>,
+    pub rom_path: String,
+    pub settings: Settings,
+    pub seed: Option<EpisodeSeed>,
+    pub options: RecordOptions,
+    pub screen_width: i32,
+    pub screen_height: i32,
+    pub ram_size: i32,
+    pub initial_state: AleSystemState,
+}
+
+impl RecordingHeader {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let preamble = Preamble {
+            rom_md5: self.rom_md5,
+            rom_path: self.rom_path.clone(),
+            settings: self.settings.clone(),
+            seed: self.seed,
+            options: self.options,
+            screen_width: self.screen_width,
+            screen_height: self.screen_height,
+            ram_size: self.ram_size,
+        };
+        try!(preamble.write_to(w));
+        self.initial_state.write_to(w)
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        let preamble = try!(Preamble::read_from(r));
+
+        Ok(RecordingHeader {
+            rom_md5: preamble.rom_md5,
+            rom_path: preamble.rom_path,
+            settings: preamble.settings,
+            seed: preamble.seed,
+            options: preamble.options,
+            screen_width: preamble.screen_width,
+            screen_height: preamble.screen_height,
+            ram_size: preamble.ram_size,
+            initial_state: try!(AleSystemState::read_from(r)),
+        })
+    }
+}
+
+/// The header up to the initial state, which is the only part that needs
+/// ALE to decode.
+#[derive(Clone, PartialEq, Debug)]
+struct Preamble {
+    rom_md5: Option<[u8; 16]>,
+    rom_path: String,
+    settings: Settings,
+    seed: Option<EpisodeSeed>,
+    options: RecordOptions,
+    screen_width: i32,
+    screen_height: i32,
+    ram_size: i32,
+}
+
+impl Preamble {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        try!(w.write_all(MAGIC));
+        try!(write_u8(w, FORMAT_VERSION));
+        match self.rom_md5 {
+            Some(md5) => {
+                try!(write_u8(w, 1));
+                try!(w.write_all(&md5));
+            },
+            None => try!(write_u8(w, 0)),
+        }
+        try!(write_str(w, &self.rom_path));
+        try!(self.settings.write_to(w));
+        try!(write_seed(w, self.seed));
+        try!(write_u8(w, self.options.to_bits()));
+        try!(write_i32(w, self.screen_width));
+        try!(write_i32(w, self.screen_height));
+        write_i32(w, self.ram_size)
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        try!(expect_magic(r, MAGIC, "an ALE recording"));
+        let version = try!(read_u8(r));
+        if version != FORMAT_VERSION {
+            return Err(Error::Format(format!("unsupported recording format version {}", version)));
+        }
+
+        let rom_md5 = match try!(read_u8(r)) {
+            0 => None,
+            _ => {
+                let mut md5 = [0u8; 16];
+                try!(r.read_exact(&mut md5));
+                Some(md5)
+            },
+        };
+
+        Ok(Preamble {
+            rom_md5: rom_md5,
+            rom_path: try!(read_str(r, 4096)),
+            settings: try!(Settings::read_from(r)),
+            seed: try!(read_seed(r)),
+            options: RecordOptions::from_bits(try!(read_u8(r))),
+            screen_width: try!(read_i32(r)),
+            screen_height: try!(read_i32(r)),
+            ram_size: try!(read_i32(r)),
+        })
+    }
+}
+
+/// A recorded step.
+#[derive(Clone, Debug)]
+pub struct StepRecord {
+    pub action: Action,
+    pub reward: i32,
+    pub lives: i32,
+    pub frame_number: i32,
+    pub episode_frame_number: i32,
+    pub terminal: bool,
+    pub sticky: bool,
+    pub ram_hash: u64,
+    pub screen: Option<Vec<u8>>,
+    pub screen_rgb: Option<Vec<u8>>,
+    pub ram: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Record {
+    /// An episode starts, including the first one.
+    Episode { index: u64, seed: Option<EpisodeSeed> },
+    Step(StepRecord),
+}
+
+/// Wraps a game, or a wrapper around one such as `StickyActions`, and
+/// records every step and reset to a writer.
+///
+/// Steps are recorded with the requested action and the `sticky` flag of
+/// the step, so a replay can repeat the previous action wherever it stuck.
+pub struct Recorder<W: Write, E: Env = Game> {
+    env: E,
+    out: W,
+    options: RecordOptions,
+    episode: u64,
+    ram: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write, E: Env> Recorder<W, E> {
+    /// Writes the header, with the game's current state as the initial
+    /// state, and starts recording episode 0.
+    pub fn new(env: E, mut out: W, options: RecordOptions) -> Result<Self, Error> {
+        let header = {
+            let game = env.game();
+            let (screen_width, screen_height) = game.screen_dimensions();
+            RecordingHeader {
+                rom_md5: game.rom_md5(),
+                rom_path: game.rom_path().to_owned(),
+                settings: Settings::capture(game),
+                seed: game.seed(),
+                options: options,
+                screen_width: screen_width,
+                screen_height: screen_height,
+                ram_size: game.ram_size(),
+                initial_state: game.clone_system_state(),
+            }
+        };
+        try!(header.write_to(&mut out));
+
+        let mut recorder = Recorder {
+            env: env,
+            out: out,
+            options: options,
+            episode: 0,
+            ram: Vec::new(),
+            buf: Vec::new(),
+        };
+        try!(recorder.write_episode_start());
+
+        Ok(recorder)
+    }
+
+    fn write_episode_start(&mut self) -> Result<(), Error> {
+        try!(write_u8(&mut self.out, EPISODE_TAG));
+        try!(write_u64(&mut self.out, self.episode));
+        try!(write_seed(&mut self.out, self.env.game().seed()));
+
+        Ok(())
+    }
+
+    /// Performs and records an action.
+    pub fn step(&mut self, action: Action) -> Result<StepInfo, Error> {
+        let info = self.env.step(action);
+        let game = self.env.game();
+        game.ram_in_buf(&mut self.ram);
+
+        let out = &mut self.out;
+        try!(write_u8(out, STEP_TAG));
+        try!(write_i32(out, action.0));
+        try!(write_i32(out, info.reward));
+        try!(write_i32(out, info.lives));
+        try!(write_i32(out, info.frame_number));
+        try!(write_i32(out, info.episode_frame_number));
+        let flags = if info.terminal { TERMINAL_FLAG } else { 0 } | if info.sticky { STICKY_FLAG } else { 0 };
+        try!(write_u8(out, flags));
+        try!(write_u64(out, fnv1a(&self.ram)));
+
+        if self.options.screen {
+            game.screen_in_buf(&mut self.buf);
+            try!(write_bytes(out, &self.buf));
+        }
+        if self.options.screen_rgb {
+            game.screen_rgb_in_buf(&mut self.buf);
+            try!(write_bytes(out, &self.buf));
+        }
+        if self.options.ram {
+            try!(write_bytes(out, &self.ram));
+        }
+
+        Ok(info)
+    }
+
+    /// Resets the game and starts recording the next episode.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.env.reset();
+        self.episode += 1;
+
+        self.write_episode_start()
+    }
+
+    /// Marks the recording as finished, flushes it and returns the wrapped
+    /// game and the writer.
+    pub fn finish(mut self) -> Result<(E, W), Error> {
+        try!(write_u8(&mut self.out, END_TAG));
+        try!(self.out.flush());
+
+        Ok((self.env, self.out))
+    }
+}
+
+impl<W: Write, E: Env> Deref for Recorder<W, E> {
+    type Target=E;
+
+    fn deref(&self) -> &E {
+        &self.env
+    }
+}
+
+/// Reads a recording written by a `Recorder`.
+pub struct RecordingReader<R: Read> {
+    r: R,
+    header: RecordingHeader,
+    finished: bool,
+    complete: bool,
+}
+
+impl<R: Read> RecordingReader<R> {
+    pub fn new(mut r: R) -> Result<Self, Error> {
+        let header = try!(RecordingHeader::read_from(&mut r));
+
+        Ok(RecordingReader {
+            r: r,
+            header: header,
+            finished: false,
+            complete: false,
+        })
+    }
+
+    pub fn header(&self) -> &RecordingHeader {
+        &self.header
+    }
+
+    /// Whether the end marker has been read. A recording that ends without
+    /// it was cut short, but every record before that point is intact.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Reads the next record, or `None` at the end of the recording.
+    pub fn next_record(&mut self) -> Result<Option<Record>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let mut tag = [0u8; 1];
+        if try!(self.r.read(&mut tag)) == 0 {
+            self.finished = true;
+            return Ok(None);
+        }
+
+        match tag[0] {
+            EPISODE_TAG => Ok(Some(Record::Episode {
+                index: try!(read_u64(&mut self.r)),
+                seed: try!(read_seed(&mut self.r)),
+            })),
+            STEP_TAG => self.read_step().map(|step| Some(Record::Step(step))),
+            END_TAG => {
+                self.finished = true;
+                self.complete = true;
+                Ok(None)
+            },
+            tag => Err(Error::Format(format!("unknown record tag {}", tag))),
+        }
+    }
+
+    fn read_step(&mut self) -> Result<StepRecord, Error> {
+        read_step(&mut self.r, self.header.options)
+    }
+
+    pub fn into_header(self) -> RecordingHeader {
+        self.header
+    }
+}
+
+/// Reads the body of a step record, after its tag.
+fn read_step<R: Read>(r: &mut R, options: RecordOptions) -> Result<StepRecord, Error> {
+    let action = Action(try!(read_i32(r)));
+    let reward = try!(read_i32(r));
+    let lives = try!(read_i32(r));
+    let frame_number = try!(read_i32(r));
+    let episode_frame_number = try!(read_i32(r));
+    let flags = try!(read_u8(r));
+    let ram_hash = try!(read_u64(r));
+
+    let screen = if options.screen { Some(try!(read_bytes(r, MAX_FIELD_LEN))) } else { None };
+    let screen_rgb = if options.screen_rgb { Some(try!(read_bytes(r, MAX_FIELD_LEN))) } else { None };
+    let ram = if options.ram { Some(try!(read_bytes(r, MAX_FIELD_LEN))) } else { None };
+
+    Ok(StepRecord {
+        action: action,
+        reward: reward,
+        lives: lives,
+        frame_number: frame_number,
+        episode_frame_number: episode_frame_number,
+        terminal: flags & TERMINAL_FLAG != 0,
+        sticky: flags & STICKY_FLAG != 0,
+        ram_hash: ram_hash,
+        screen: screen,
+        screen_rgb: screen_rgb,
+        ram: ram,
+    })
+}
+
+impl<R: Read> Iterator for RecordingReader<R> {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Result<Record, Error>> {
+        match self.next_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(err) => {
+                self.finished = true;
+                Some(Err(err))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::Action;
+    use ::bytes::*;
+    use super::*;
+
+    fn encoded_step(options: RecordOptions) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_i32(&mut buf, 3).unwrap();
+        write_i32(&mut buf, 10).unwrap();
+        write_i32(&mut buf, 2).unwrap();
+        write_i32(&mut buf, 400).unwrap();
+        write_i32(&mut buf, 100).unwrap();
+        write_u8(&mut buf, TERMINAL_FLAG | STICKY_FLAG).unwrap();
+        write_u64(&mut buf, 0xDEADBEEF).unwrap();
+        if options.screen {
+            write_bytes(&mut buf, &[1, 2, 3]).unwrap();
+        }
+        if options.screen_rgb {
+            write_bytes(&mut buf, &[4, 5, 6, 7, 8, 9]).unwrap();
+        }
+        if options.ram {
+            write_bytes(&mut buf, &[0xAA; 128]).unwrap();
+        }
+
+        buf
+    }
+
+    #[test]
+    fn options_round_trip_through_bits() {
+        for bits in 0..8 {
+            assert_eq!(RecordOptions::from_bits(bits).to_bits(), bits);
+        }
+    }
+
+    #[test]
+    fn step_round_trips() {
+        let options = RecordOptions { screen: true, screen_rgb: true, ram: true };
+        let step = read_step(&mut &encoded_step(options)[..], options).unwrap();
+
+        assert_eq!(step.action, Action(3));
+        assert_eq!((step.reward, step.lives, step.frame_number, step.episode_frame_number), (10, 2, 400, 100));
+        assert!(step.terminal && step.sticky);
+        assert_eq!(step.ram_hash, 0xDEADBEEF);
+        assert_eq!(step.screen, Some(vec![1, 2, 3]));
+        assert_eq!(step.screen_rgb, Some(vec![4, 5, 6, 7, 8, 9]));
+        assert_eq!(step.ram, Some(vec![0xAA; 128]));
+    }
+
+    #[test]
+    fn truncated_steps_are_rejected() {
+        let options = RecordOptions { screen: true, screen_rgb: false, ram: true };
+        let bytes = encoded_step(options);
+
+        for len in 0..bytes.len() {
+            assert!(read_step(&mut &bytes[..len], options).is_err(), "accepted a step cut to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn oversized_fields_are_rejected() {
+        let options = RecordOptions { screen: true, screen_rgb: false, ram: false };
+        let mut bytes = encoded_step(RecordOptions::default());
+        write_u64(&mut bytes, u64::max_value()).unwrap();
+
+        match read_step(&mut &bytes[..], options) {
+            Err(Error::Format(_)) => {},
+            other => panic!("expected a format error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn preamble() -> Preamble {
+        Preamble {
+            rom_md5: Some([7; 16]),
+            rom_path: "roms/pong.bin".to_owned(),
+            settings: Settings {
+                random_seed: 42,
+                frame_skip: 4,
+                max_num_frames: 0,
+                max_num_frames_per_episode: 108000,
+                repeat_action_probability: 0.25,
+                color_averaging: true,
+                display_screen: false,
+                sound: false,
+                record_screen_dir: "frames".to_owned(),
+            },
+            seed: Some(EpisodeSeed::new(1, 2, 3)),
+            options: RecordOptions { screen: true, screen_rgb: false, ram: true },
+            screen_width: 160,
+            screen_height: 210,
+            ram_size: 128,
+        }
+    }
+
+    #[test]
+    fn header_preamble_round_trips() {
+        for &(rom_md5, seed) in &[(Some([7; 16]), Some(EpisodeSeed::new(1, 2, 3))), (None, None)] {
+            let preamble = Preamble { rom_md5: rom_md5, seed: seed, ..preamble() };
+            let mut bytes = Vec::new();
+            preamble.write_to(&mut bytes).unwrap();
+
+            let mut r = &bytes[..];
+            assert_eq!(Preamble::read_from(&mut r).unwrap(), preamble);
+            assert!(r.is_empty());
+        }
+    }
+
+    #[test]
+    fn truncated_header_preambles_are_rejected() {
+        let mut bytes = Vec::new();
+        preamble().write_to(&mut bytes).unwrap();
+
+        for len in 0..bytes.len() {
+            assert!(Preamble::read_from(&mut &bytes[..len]).is_err(), "accepted a header cut to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        assert!(RecordingReader::new(&b""[..]).is_err());
+        assert!(RecordingReader::new(&b"ALERAC\x01"[..]).is_err());
+
+        let mut future = MAGIC.to_vec();
+        future.push(FORMAT_VERSION + 1);
+        match RecordingReader::new(&future[..]) {
+            Err(Error::Format(ref msg)) => assert!(msg.contains("version")),
+            other => panic!("expected a version error, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Finding ROMs on disk by game name.
+//!
+//! A `RomRegistry` scans directories for ROM files and identifies each one
+//! by the MD5 of its contents against `KNOWN_ROMS`, the hashes of the
+//! standard ALE ROM set, so file names don't matter:
+//!
+//! ```no_run
+//! use ale::{Ale,RomRegistry};
+//!
+//! let registry = RomRegistry::scan_dirs(&["./roms"]).unwrap();
+//! let game = registry.load(Ale::new(), "pong").unwrap();
+//! ```
+use std::collections::{BTreeMap,BTreeSet};
+use std::fs::{self,File};
+use std::io::Read;
+use std::path::{Path,PathBuf};
+use ::{Ale,Error,Game,RomCache};
+use ::bytes::file_md5;
+use ::serialize::Rom;
+
+/// An entry in the table of known Atari 2600 ROMs.
+#[derive(Debug)]
+pub struct KnownRom {
+    /// The canonical id, which is also the file stem ALE expects.
+    pub id: &'static str,
+    pub name: &'static str,
+    pub md5: &'static str,
+    /// The game modes ALE supports for this ROM.
+    pub modes: &'static [i32],
+}
+
+pub static KNOWN_ROMS: &'static [KnownRom] = &[
+    KnownRom { id: "alien", name: "Alien", md5: "f1a0a23e6464d954e3a9579c4ccd01c8", modes: &[0] },
+    KnownRom { id: "amidar", name: "Amidar", md5: "acb7750b4d0c4bd34969802a7deb2990", modes: &[0] },
+    KnownRom { id: "assault", name: "Assault", md5: "de78b3a064d374390ac0710f95edde92", modes: &[0] },
+    KnownRom { id: "asterix", name: "Asterix", md5: "89a68746eff7f266bbf08de2483abe55", modes: &[0] },
+    KnownRom { id: "atlantis", name: "Atlantis", md5: "9ad36e699ef6f45d9eb6c4cf90475c9f", modes: &[0] },
+    KnownRom { id: "bank_heist", name: "Bank Heist", md5: "00ce0bdd43aed84a983bef38fe7f5ee3", modes: &[0] },
+    KnownRom { id: "battle_zone", name: "Battlezone", md5: "41f252a66c6301f1e8ab3612c19bc5d4", modes: &[0] },
+    KnownRom { id: "beam_rider", name: "Beamrider", md5: "79ab4123a83dc11d468fb2108ea09e2e", modes: &[0] },
+    KnownRom { id: "bowling", name: "Bowling", md5: "c9b7afad3bfd922e006a6bfc1d4f3fe7", modes: &[0] },
+    KnownRom { id: "boxing", name: "Boxing", md5: "c3ef5c4653212088eda54dc91d787870", modes: &[0, 1, 2, 3] },
+    KnownRom { id: "breakout", name: "Breakout", md5: "f34f08e5eb96e500e851a80be3277a56",
+               modes: &[0, 4, 8, 12, 16, 20, 24, 28, 32, 36, 40, 44] },
+    KnownRom { id: "centipede", name: "Centipede", md5: "91c2098e88a6b13f977af8c003e0bca5", modes: &[0] },
+    KnownRom { id: "chopper_command", name: "Chopper Command", md5: "c1cb228470a87beb5f36e90ac745da26", modes: &[0] },
+    KnownRom { id: "crazy_climber", name: "Crazy Climber", md5: "55ef7b65066428367844342ed59f956c", modes: &[0] },
+    KnownRom { id: "demon_attack", name: "Demon Attack", md5: "f0e0addc07971561ab80d9abe1b8d333", modes: &[0] },
+    KnownRom { id: "enduro", name: "Enduro", md5: "94b92a882f6dbaa6993a46e2dcc58402", modes: &[0] },
+    KnownRom { id: "freeway", name: "Freeway", md5: "8e0ab801b1705a740b476b7f588c6d16", modes: &[0, 1, 2, 3, 4, 5, 6, 7] },
+    KnownRom { id: "frostbite", name: "Frostbite", md5: "4ca73eb959299471788f0b685c3ba0b5", modes: &[0] },
+    KnownRom { id: "gopher", name: "Gopher", md5: "c16c79aad6272baffb8aae9a7fff0864", modes: &[0] },
+    KnownRom { id: "gravitar", name: "Gravitar", md5: "8ac18076d01a6b63acf6e2cab4968940", modes: &[0] },
+    KnownRom { id: "hero", name: "H.E.R.O.", md5: "fca4a5be1251927027f2c24774a02160", modes: &[0] },
+    KnownRom { id: "kangaroo", name: "Kangaroo", md5: "4326edb70ff20d0ee5ba58fa5cb09d60", modes: &[0] },
+    KnownRom { id: "krull", name: "Krull", md5: "4baada22435320d185c95b7dd2bcdb24", modes: &[0] },
+    KnownRom { id: "kung_fu_master", name: "Kung-Fu Master", md5: "5b92a93b23523ff16e2789b820e2a4c5", modes: &[0] },
+    KnownRom { id: "montezuma_revenge", name: "Montezuma's Revenge", md5: "3e90cf23106f2e08b2781e41299de556", modes: &[0] },
+    KnownRom { id: "ms_pacman", name: "Ms. Pac-Man", md5: "87e79cd41ce136fd4f72cc6e2c161bcc", modes: &[0, 1, 2, 3] },
+    KnownRom { id: "pong", name: "Pong", md5: "60e0ea3cbe0913d39803477945e9e5ec", modes: &[0, 1] },
+    KnownRom { id: "private_eye", name: "Private Eye", md5: "ef3a4f64b6494ba770862768caf04b86", modes: &[0] },
+    KnownRom { id: "qbert", name: "Q*bert", md5: "484b0076816a104875e00467d431c2d2", modes: &[0] },
+    KnownRom { id: "riverraid", name: "River Raid", md5: "393948436d1f4cc3192410bb918f9724", modes: &[0] },
+    KnownRom { id: "road_runner", name: "Road Runner", md5: "ce89529d6e98a13ddf3d84827bbdfe68", modes: &[0] },
+    KnownRom { id: "robotank", name: "Robot Tank", md5: "4f618c2429138e0280969193ed6c107e", modes: &[0] },
+    KnownRom { id: "seaquest", name: "Seaquest", md5: "240bfbac5163af4df5ae713985386f92", modes: &[0] },
+    KnownRom { id: "skiing", name: "Skiing", md5: "b76fbadc8ffb1f83e2ca08b6fb4d6c9f", modes: &[0] },
+    KnownRom { id: "solaris", name: "Solaris", md5: "e72eb8d4410152bdcb69e7fba327b420", modes: &[0] },
+    KnownRom { id: "space_invaders", name: "Space Invaders", md5: "72ffbef6504b75e69ee1045af9075f66",
+               modes: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15] },
+    KnownRom { id: "tennis", name: "Tennis", md5: "42cdd6a9e42a3639e190722b8ea3fc51", modes: &[0] },
+    KnownRom { id: "tutankham", name: "Tutankham", md5: "085322bae40d904f53bdcc56df0593fc", modes: &[0] },
+    KnownRom { id: "venture", name: "Venture", md5: "3e899eba0ca8cd2972da1ae5479b4f0d", modes: &[0] },
+    KnownRom { id: "video_pinball", name: "Video Pinball", md5: "107cc025334211e6d29da0b6be46aec7", modes: &[0] },
+    KnownRom { id: "wizard_of_wor", name: "Wizard of Wor", md5: "7e8aa18bc9502eb57daaf5e7c1e94da7", modes: &[0] },
+    KnownRom { id: "zaxxon", name: "Zaxxon", md5: "eea0da9b987d661264cce69a7c13c3bd", modes: &[0] },
+];
+
+/// Files with these extensions are considered ROMs when scanning.
+const ROM_EXTENSIONS: &'static [&'static str] = &["bin", "a26", "rom"];
+/// Atari 2600 cartridges are at most a few dozen KiB, skip anything larger.
+const MAX_ROM_SIZE: u64 = 1 << 20;
+
+pub fn known_by_md5(md5: &str) -> Option<&'static KnownRom> {
+    KNOWN_ROMS.iter().find(|rom| rom.md5 == md5)
+}
+
+pub fn known_by_id(id: &str) -> Option<&'static KnownRom> {
+    KNOWN_ROMS.iter().find(|rom| rom.id == id)
+}
+
+/// A ROM file that was identified while scanning.
+#[derive(Debug)]
+pub struct RegisteredRom {
+    pub path: PathBuf,
+    pub known: &'static KnownRom,
+}
+
+/// A file that was skipped while scanning.
+#[derive(Debug)]
+pub enum ScanIssue {
+    /// The file's MD5 isn't in `KNOWN_ROMS`.
+    Unknown { path: PathBuf, md5: String },
+    /// The file is named after a known game but its MD5 doesn't match it,
+    /// e.g. a bad dump or a different revision.
+    Mismatched { path: PathBuf, id: &'static str, md5: String },
+    /// The game was already found at another path, which is kept.
+    Duplicate { path: PathBuf, id: &'static str },
+    Unreadable { path: PathBuf, error: String },
+}
+
+pub struct RomRegistry {
+    roms: BTreeMap<&'static str, RegisteredRom>,
+    issues: Vec<ScanIssue>,
+}
+
+impl RomRegistry {
+    pub fn new() -> Self {
+        RomRegistry {
+            roms: BTreeMap::new(),
+            issues: Vec::new(),
+        }
+    }
+
+    /// Creates a registry and scans each of the given directories.
+    pub fn scan_dirs<P: AsRef<Path>>(dirs: &[P]) -> Result<Self, Error> {
+        let mut registry = RomRegistry::new();
+        for dir in dirs {
+            try!(registry.scan(dir));
+        }
+
+        Ok(registry)
+    }
+
+    /// Scans a directory and its subdirectories for ROMs. Only errors
+    /// reading the directories themselves are returned, problems with
+    /// individual files are recorded in `issues`. Symbolic links are
+    /// followed, but each directory is scanned at most once, so links that
+    /// form a loop are harmless.
+    pub fn scan<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), Error> {
+        let mut visited = BTreeSet::new();
+        self.scan_dir(dir.as_ref(), &mut visited)
+    }
+
+    fn scan_dir(&mut self, dir: &Path, visited: &mut BTreeSet<PathBuf>) -> Result<(), Error> {
+        if !visited.insert(try!(fs::canonicalize(dir))) {
+            return Ok(());
+        }
+
+        let mut entries = Vec::new();
+        for entry in try!(fs::read_dir(dir)) {
+            entries.push(try!(entry).path());
+        }
+        entries.sort();
+
+        for path in entries {
+            if path.is_dir() {
+                try!(self.scan_dir(&path, visited));
+            } else if has_rom_extension(&path) {
+                self.add_file(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_file(&mut self, path: PathBuf) {
+        let md5 = match file_md5(&path, MAX_ROM_SIZE) {
+            Ok(md5) => md5,
+            Err(err) => {
+                self.issues.push(ScanIssue::Unreadable { path: path, error: err.to_string() });
+                return;
+            },
+        };
+
+        let known = match known_by_md5(&md5) {
+            Some(known) => known,
+            None => {
+                let named = path.file_stem().and_then(|stem| stem.to_str())
+                                .and_then(|stem| known_by_id(&stem.to_lowercase()));
+                self.issues.push(match named {
+                    Some(known) => ScanIssue::Mismatched { path: path, id: known.id, md5: md5 },
+                    None => ScanIssue::Unknown { path: path, md5: md5 },
+                });
+                return;
+            },
+        };
+
+        if self.roms.contains_key(known.id) {
+            self.issues.push(ScanIssue::Duplicate { path: path, id: known.id });
+        } else {
+            self.roms.insert(known.id, RegisteredRom { path: path, known: known });
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&RegisteredRom> {
+        self.roms.get(id)
+    }
+
+    /// The ids of all games found, in sorted order.
+    pub fn ids(&self) -> Vec<&'static str> {
+        self.roms.keys().cloned().collect()
+    }
+
+    pub fn roms(&self) -> Vec<&RegisteredRom> {
+        self.roms.values().collect()
+    }
+
+    pub fn issues(&self) -> &[ScanIssue] {
+        &self.issues
+    }
+
+    /// Loads the game with the given id. ALE picks a game's settings by the
+    /// ROM's file name, so a ROM saved under another name is first copied
+    /// into `RomCache::from_env` under its canonical one.
+    pub fn load(&self, ale: Ale, id: &str) -> Result<Game, Error> {
+        let rom = match self.roms.get(id) {
+            Some(rom) => rom,
+            None => return Err(Error::UnknownRom(id.to_owned())),
+        };
+
+        let canonical = rom.path.file_stem().and_then(|stem| stem.to_str()) == Some(rom.known.id);
+        let path = if canonical {
+            rom.path.clone()
+        } else {
+            let mut data = Vec::new();
+            try!(try!(File::open(&rom.path)).read_to_end(&mut data));
+
+            let renamed = Rom {
+                rom_path: PathBuf::from(format!("{}.bin", rom.known.id)),
+                data: data,
+            };
+            try!(RomCache::from_env().store(&renamed))
+        };
+
+        Ok(ale.load_rom(path.to_str().expect("Could not decode path to string")))
+    }
+}
+
+impl Default for RomRegistry {
+    fn default() -> Self {
+        RomRegistry::new()
+    }
+}
+
+fn has_rom_extension(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ROM_EXTENSIONS.contains(&&*ext.to_lowercase()),
+        None => false,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::env;
+    use std::fs::{self,File};
+    use std::io::Write;
+    use std::os::unix::fs::symlink;
+    use std::process;
+    use super::*;
+
+    #[test]
+    fn scan_survives_symlink_loops() {
+        let root = env::temp_dir().join(format!("ale-registry-test-{}", process::id()));
+        let nested = root.join("nested");
+        fs::create_dir_all(&nested).unwrap();
+        symlink(&root, nested.join("loop")).unwrap();
+        File::create(nested.join("junk.bin")).unwrap().write_all(b"not a rom").unwrap();
+
+        let registry = RomRegistry::scan_dirs(&[&root]);
+        fs::remove_dir_all(&root).unwrap();
+
+        let registry = registry.unwrap();
+        assert!(registry.ids().is_empty());
+        assert_eq!(registry.issues().len(), 1);
+        match registry.issues()[0] {
+            ScanIssue::Unknown { ref md5, .. } => assert_eq!(md5, &format!("{:x}", ::md5::compute(b"not a rom"))),
+            ref issue => panic!("unexpected issue {:?}", issue),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Replaying recordings to check that they are reproducible.
+//!
+//! A `Replayer` rebuilds the recorded game on a fresh `Ale`: it applies the
+//! recorded settings that affect determinism, leaving display and recording
+//! settings alone, loads the ROM with the recorded episode seed and restores
+//! the initial system state. It then re-issues every recorded action
+//! through `Game::act` and compares the reward and the hash of the RAM with
+//! the recording, stopping at the first step that differs. Steps recorded as
+//! sticky, see `StickyActions`, repeat the previously performed action
+//! instead. A divergence usually means nondeterminism, e.g. from ALE's own
+//! `repeat_action_probability`, or a change in behaviour after upgrading ALE.
+use std::io::Read;
+use ::{Action,Ale,Error,Game};
+use ::bytes::fnv1a;
+use ::record::{Record,RecordingHeader,RecordingReader,StepRecord};
+use ::seed::EpisodeSeed;
+
+/// What differed between the recording and the replay.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Mismatch {
+    Reward { recorded: i32, replayed: i32 },
+    RamHash { recorded: u64, replayed: u64 },
+    /// An episode started with a different seed than the recorded one.
+    Seed { recorded: Option<EpisodeSeed>, replayed: Option<EpisodeSeed> },
+}
+
+/// The first point at which a replay differed from its recording.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Divergence {
+    /// The index of the episode in the recording, counting from 0.
+    pub episode: u64,
+    /// The step within that episode, counting from 0.
+    pub step: u64,
+    /// The step since the start of the recording, counting from 0.
+    pub total_step: u64,
+    pub mismatch: Mismatch,
+}
+
+/// The result of replaying a whole recording.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct ReplayReport {
+    pub episodes: u64,
+    pub steps: u64,
+    /// Whether the recording was finished, rather than cut short.
+    pub complete: bool,
+    pub divergence: Option<Divergence>,
+}
+
+impl ReplayReport {
+    pub fn is_deterministic(&self) -> bool {
+        self.divergence.is_none()
+    }
+}
+
+pub struct Replayer {
+    game: Game,
+    episodes: u64,
+    step: u64,
+    total_step: u64,
+    last_action: Action,
+    ram: Vec<u8>,
+}
+
+impl Replayer {
+    /// Sets up a replay of the recording with the given header, loading the
+    /// ROM from the path it was recorded with.
+    pub fn new(ale: Ale, header: &RecordingHeader) -> Result<Self, Error> {
+        Replayer::with_rom(ale, header, &header.rom_path)
+    }
+
+    /// Sets up a replay of the recording with the given header, loading the
+    /// ROM from `rom_path`. The ROM must be the one that was recorded.
+    pub fn with_rom(mut ale: Ale, header: &RecordingHeader, rom_path: &str) -> Result<Self, Error> {
+        header.settings.apply_deterministic(&mut ale);
+        let mut game = match header.seed {
+            Some(seed) => ale.load_rom_with_seed(rom_path, seed),
+            None => ale.load_rom(rom_path),
+        };
+
+        if let (Some(recorded), Some(loaded)) = (header.rom_md5, game.rom_md5()) {
+            if recorded != loaded {
+                return Err(Error::RomMismatch { state: recorded, game: loaded });
+            }
+        }
+        try!(game.restore_from_cloned_system_state(&header.initial_state));
+
+        Ok(Replayer {
+            game: game,
+            episodes: 0,
+            step: 0,
+            total_step: 0,
+            last_action: Action(0),
+            ram: Vec::new(),
+        })
+    }
+
+    /// Replays a single record, returning how it differed from the
+    /// recording, if at all.
+    pub fn replay(&mut self, record: &Record) -> Option<Divergence> {
+        match *record {
+            Record::Episode { seed, .. } => self.start_episode(seed),
+            Record::Step(ref step) => self.replay_step(step),
+        }
+    }
+
+    fn start_episode(&mut self, seed: Option<EpisodeSeed>) -> Option<Divergence> {
+        // The first episode starts from the restored initial state.
+        if self.episodes > 0 {
+            self.game.reset();
+        }
+        self.episodes += 1;
+        self.step = 0;
+        // Like StickyActions, which forgets the previous action on reset.
+        // A recording that starts mid-episode assumes the same.
+        self.last_action = Action(0);
+
+        if seed != self.game.seed() {
+            return Some(self.divergence(Mismatch::Seed { recorded: seed, replayed: self.game.seed() }));
+        }
+
+        None
+    }
+
+    fn replay_step(&mut self, step: &StepRecord) -> Option<Divergence> {
+        let action = if step.sticky { self.last_action } else { step.action };
+        self.last_action = action;
+
+        let reward = self.game.act(action);
+        self.game.ram_in_buf(&mut self.ram);
+        let ram_hash = fnv1a(&self.ram);
+
+        let mismatch = if reward != step.reward {
+            Some(Mismatch::Reward { recorded: step.reward, replayed: reward })
+        } else if ram_hash != step.ram_hash {
+            Some(Mismatch::RamHash { recorded: step.ram_hash, replayed: ram_hash })
+        } else {
+            None
+        };
+
+        let divergence = mismatch.map(|mismatch| self.divergence(mismatch));
+        self.step += 1;
+        self.total_step += 1;
+
+        divergence
+    }
+
+    fn divergence(&self, mismatch: Mismatch) -> Divergence {
+        Divergence {
+            episode: self.episodes.saturating_sub(1),
+            step: self.step,
+            total_step: self.total_step,
+            mismatch: mismatch,
+        }
+    }
+
+    /// Replays the remaining records of a recording, stopping at the first
+    /// divergence.
+    pub fn run<R: Read>(&mut self, reader: &mut RecordingReader<R>) -> Result<ReplayReport, Error> {
+        let mut divergence = None;
+        while let Some(record) = try!(reader.next_record()) {
+            divergence = self.replay(&record);
+            if divergence.is_some() {
+                break;
+            }
+        }
+
+        Ok(ReplayReport {
+            episodes: self.episodes,
+            steps: self.total_step,
+            complete: reader.is_complete(),
+            divergence: divergence,
+        })
+    }
+
+    /// The game being replayed, in the state after the last replayed record.
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn into_game(self) -> Game {
+        self.game
+    }
+}
+
+/// Replays a whole recording on a fresh `Ale`.
+pub fn verify<R: Read>(ale: Ale, r: R) -> Result<ReplayReport, Error> {
+    let mut reader = try!(RecordingReader::new(r));
+    let mut replayer = try!(Replayer::new(ale, reader.header()));
+
+    replayer.run(&mut reader)
+}
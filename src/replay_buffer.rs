@@ -0,0 +1,757 @@
+//! An experience replay buffer for agents trained on stacked frames.
+//!
+//! Each transition stores a single frame, the observation before its action,
+//! so a stack of `stack` frames costs no more memory than one frame. Stacks
+//! are rebuilt when sampling: frames from before the start of the episode
+//! are zero, like at the start of a real episode.
+//!
+//! Transitions are added in order with `add`. A transition can be sampled
+//! once the `n_step - 1` transitions after it, and the frame after those,
+//! have been added, or the episode ended in between. The next state of a
+//! transition that ends its episode is zero.
+use std::io::{self,Read,Write};
+use ::{Action,Error};
+use ::bytes::*;
+use ::seed::Rng;
+
+const MAGIC: &'static [u8] = b"ALE-REPLAY";
+const FORMAT_VERSION: u8 = 1;
+
+/// How many times a sample is redrawn before giving up because the buffer
+/// has too few valid transitions.
+const MAX_ATTEMPTS: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ReplayConfig {
+    /// The number of transitions kept, the oldest are overwritten first.
+    pub capacity: usize,
+    /// The size of a single frame in bytes, e.g. 84 * 84.
+    pub frame_len: usize,
+    /// The number of frames in a state.
+    pub stack: usize,
+    /// The number of rewards summed into a return.
+    pub n_step: usize,
+    /// The discount applied to each step of a return.
+    pub gamma: f32,
+    /// Samples transitions by priority instead of uniformly.
+    pub priority: Option<PriorityConfig>,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        ReplayConfig {
+            capacity: 1000000,
+            frame_len: 84 * 84,
+            stack: 4,
+            n_step: 1,
+            gamma: 0.99,
+            priority: None,
+        }
+    }
+}
+
+/// Parameters of proportional prioritized replay, where a transition is
+/// sampled with probability proportional to `(|td_error| + epsilon)^alpha`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PriorityConfig {
+    pub alpha: f64,
+    pub epsilon: f64,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        PriorityConfig {
+            alpha: 0.6,
+            epsilon: 1e-6,
+        }
+    }
+}
+
+/// A batch of sampled transitions. States are stored back to back, each
+/// `stack * frame_len` bytes with the oldest frame first.
+#[derive(Clone, Debug, Default)]
+pub struct Batch {
+    /// Where each transition is stored, for `update_priorities`.
+    pub indices: Vec<usize>,
+    pub states: Vec<u8>,
+    pub actions: Vec<Action>,
+    /// The discounted sum of up to `n_step` rewards.
+    pub returns: Vec<f32>,
+    pub next_states: Vec<u8>,
+    /// Whether the episode ended within the return.
+    pub dones: Vec<bool>,
+    /// The discount to apply to the value of the next state, `gamma` to the
+    /// power of the number of rewards in the return.
+    pub discounts: Vec<f32>,
+    /// Importance sampling weights, normalized so the largest in the batch
+    /// is 1. All 1 when sampling uniformly.
+    pub weights: Vec<f32>,
+}
+
+impl Batch {
+    fn clear(&mut self) {
+        self.indices.clear();
+        self.states.clear();
+        self.actions.clear();
+        self.returns.clear();
+        self.next_states.clear();
+        self.dones.clear();
+        self.discounts.clear();
+        self.weights.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+/// A binary tree in which each node holds the sum of its children, for
+/// sampling leaves in proportion to their values in logarithmic time.
+#[derive(Clone, Debug)]
+pub struct SumTree {
+    leaves: usize,
+    nodes: Vec<f64>,
+}
+
+impl SumTree {
+    pub fn new(capacity: usize) -> Self {
+        let leaves = capacity.next_power_of_two();
+
+        SumTree {
+            leaves: leaves,
+            nodes: vec![0.0; 2 * leaves],
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.nodes[1]
+    }
+
+    pub fn get(&self, i: usize) -> f64 {
+        self.nodes[self.leaves + i]
+    }
+
+    pub fn set(&mut self, i: usize, value: f64) {
+        let mut node = self.leaves + i;
+        self.nodes[node] = value;
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+
+    /// The leaf where the running sum of values passes `mass`.
+    pub fn find(&self, mut mass: f64) -> usize {
+        let mut node = 1;
+        while node < self.leaves {
+            let left = self.nodes[2 * node];
+            if mass < left {
+                node = 2 * node;
+            } else {
+                mass -= left;
+                node = 2 * node + 1;
+            }
+        }
+
+        node - self.leaves
+    }
+
+    /// The number of leaves that can be set.
+    pub fn capacity(&self) -> usize {
+        self.leaves
+    }
+
+    /// Makes room for at least `capacity` leaves, keeping their values.
+    pub fn grow(&mut self, capacity: usize) {
+        if capacity <= self.leaves {
+            return;
+        }
+
+        let mut grown = SumTree::new(capacity);
+        for i in 0..self.leaves {
+            grown.set(i, self.get(i));
+        }
+        *self = grown;
+    }
+}
+
+struct Priorities {
+    config: PriorityConfig,
+    tree: SumTree,
+    max: f64,
+}
+
+pub struct ReplayBuffer {
+    config: ReplayConfig,
+    frames: Vec<u8>,
+    actions: Vec<Action>,
+    rewards: Vec<f32>,
+    terminals: Vec<bool>,
+    cursor: usize,
+    len: usize,
+    priorities: Option<Priorities>,
+}
+
+impl ReplayBuffer {
+    /// Creates an empty buffer, allocating all of its memory up front.
+    pub fn new(config: ReplayConfig) -> Self {
+        assert!(config.capacity > 0, "ReplayBuffer capacity must be positive");
+        assert!(config.stack > 0 && config.n_step > 0, "ReplayBuffer stack and n_step must be positive");
+        let frames_len = config.capacity.checked_mul(config.frame_len).expect("ReplayBuffer is too large");
+
+        ReplayBuffer {
+            config: config,
+            frames: vec![0u8; frames_len],
+            actions: vec![Action(0); config.capacity],
+            rewards: vec![0.0; config.capacity],
+            terminals: vec![false; config.capacity],
+            cursor: 0,
+            len: 0,
+            priorities: config.priority.map(|priority| Priorities {
+                config: priority,
+                tree: SumTree::new(config.capacity),
+                max: 1.0,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &ReplayConfig {
+        &self.config
+    }
+
+    /// The number of transitions stored.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a transition: the frame observed before taking `action`, the
+    /// reward for it and whether it ended the episode. Panics if the frame
+    /// isn't `frame_len` bytes long.
+    pub fn add(&mut self, frame: &[u8], action: Action, reward: f32, terminal: bool) {
+        let frame_len = self.config.frame_len;
+        assert_eq!(frame.len(), frame_len, "frame has the wrong length for this ReplayBuffer");
+
+        let i = self.cursor;
+        // A buffer from `read_from` only has room for the transitions it
+        // held, and grows until it is full.
+        if i < self.actions.len() {
+            self.frames[i * frame_len..(i + 1) * frame_len].copy_from_slice(frame);
+            self.actions[i] = action;
+            self.rewards[i] = reward;
+            self.terminals[i] = terminal;
+        } else {
+            self.frames.extend_from_slice(frame);
+            self.actions.push(action);
+            self.rewards.push(reward);
+            self.terminals.push(terminal);
+        }
+        if let Some(ref mut priorities) = self.priorities {
+            if i >= priorities.tree.capacity() {
+                priorities.tree.grow(::std::cmp::min(2 * priorities.tree.capacity(), self.config.capacity));
+            }
+            priorities.tree.set(i, priorities.max);
+        }
+
+        self.cursor = (self.cursor + 1) % self.config.capacity;
+        if self.len < self.config.capacity {
+            self.len += 1;
+        }
+    }
+
+    fn oldest(&self) -> usize {
+        if self.len == self.config.capacity { self.cursor } else { 0 }
+    }
+
+    /// The position of a stored transition counting from the oldest.
+    fn age(&self, i: usize) -> usize {
+        (i + self.config.capacity - self.oldest()) % self.config.capacity
+    }
+
+    fn index(&self, i: usize, offset: isize) -> usize {
+        let capacity = self.config.capacity as isize;
+        ((i as isize + offset) % capacity + capacity) as usize % self.config.capacity
+    }
+
+    /// The number of transitions in the return of `i` and whether the episode
+    /// ended within them, or `None` if they haven't all been added yet.
+    fn return_len(&self, i: usize) -> Option<(usize, bool)> {
+        let age = self.age(i);
+        for step in 0..self.config.n_step {
+            if age + step >= self.len {
+                return None;
+            }
+            if self.terminals[self.index(i, step as isize)] {
+                return Some((step + 1, true));
+            }
+        }
+
+        // The frame after the return is needed for the next state.
+        if age + self.config.n_step >= self.len {
+            return None;
+        }
+
+        Some((self.config.n_step, false))
+    }
+
+    /// Whether the frames before `i` in its episode are still stored.
+    fn has_history(&self, i: usize) -> bool {
+        if self.len < self.config.capacity {
+            return true;
+        }
+
+        let age = self.age(i);
+        for back in 1..self.config.stack {
+            if back > age {
+                return false;
+            }
+            if self.terminals[self.index(i, -(back as isize))] {
+                return true;
+            }
+        }
+
+        true
+    }
+
+    fn is_valid(&self, i: usize) -> bool {
+        i < self.len && self.has_history(i) && self.return_len(i).is_some()
+    }
+
+    /// Appends the stack of frames ending at `i` to `out`.
+    fn push_stack(&self, i: usize, out: &mut Vec<u8>) {
+        let frame_len = self.config.frame_len;
+        let stack = self.config.stack;
+        let start = out.len();
+        out.resize(start + stack * frame_len, 0);
+
+        let age = self.age(i);
+        for back in 0..stack {
+            if back > age {
+                break;
+            }
+            let j = self.index(i, -(back as isize));
+            if back > 0 && self.terminals[j] {
+                break;
+            }
+
+            let slot = start + (stack - 1 - back) * frame_len;
+            out[slot..slot + frame_len].copy_from_slice(&self.frames[j * frame_len..(j + 1) * frame_len]);
+        }
+    }
+
+    fn push_transition(&self, i: usize, weight: f32, batch: &mut Batch) {
+        let (steps, done) = self.return_len(i).expect("sampled a transition without a complete return");
+
+        let mut ret = 0.0;
+        let mut discount = 1.0;
+        for step in 0..steps {
+            ret += discount * self.rewards[self.index(i, step as isize)];
+            discount *= self.config.gamma;
+        }
+
+        batch.indices.push(i);
+        self.push_stack(i, &mut batch.states);
+        batch.actions.push(self.actions[i]);
+        batch.returns.push(ret);
+        if done {
+            let len = batch.next_states.len();
+            batch.next_states.resize(len + self.config.stack * self.config.frame_len, 0);
+        } else {
+            self.push_stack(self.index(i, steps as isize), &mut batch.next_states);
+        }
+        batch.dones.push(done);
+        batch.discounts.push(discount);
+        batch.weights.push(weight);
+    }
+
+    /// Samples a batch of transitions, uniformly or by priority. `beta` is
+    /// the exponent of the importance sampling weights and is ignored when
+    /// sampling uniformly. Returns `None` if too few transitions can be
+    /// sampled yet.
+    pub fn sample(&self, batch_size: usize, beta: f64, rng: &mut Rng) -> Option<Batch> {
+        let mut batch = Batch::default();
+        if self.sample_into(batch_size, beta, rng, &mut batch) {
+            Some(batch)
+        } else {
+            None
+        }
+    }
+
+    /// Like `sample`, but reuses the memory of an existing batch. Returns
+    /// false if too few transitions can be sampled yet.
+    pub fn sample_into(&self, batch_size: usize, beta: f64, rng: &mut Rng, batch: &mut Batch) -> bool {
+        batch.clear();
+        if self.len == 0 {
+            return false;
+        }
+
+        match self.priorities {
+            None => {
+                let oldest = self.oldest();
+                for _ in 0..batch_size {
+                    let found = (0..MAX_ATTEMPTS)
+                        .map(|_| (oldest + rng.below(self.len as u64) as usize) % self.config.capacity)
+                        .find(|&i| self.is_valid(i));
+                    match found {
+                        Some(i) => self.push_transition(i, 1.0, batch),
+                        None => return false,
+                    }
+                }
+            },
+            Some(ref priorities) => {
+                let total = priorities.tree.total();
+                if total <= 0.0 {
+                    return false;
+                }
+
+                // Stratified sampling, one draw from each equal slice of the
+                // total priority.
+                let segment = total / batch_size as f64;
+                let mut weights = Vec::with_capacity(batch_size);
+                for k in 0..batch_size {
+                    let found = (0..MAX_ATTEMPTS)
+                        .map(|attempt| {
+                            // Fall back to the whole range if the slice has
+                            // nothing valid in it.
+                            let mass = if attempt < MAX_ATTEMPTS / 2 {
+                                segment * (k as f64 + rng.next_f64())
+                            } else {
+                                total * rng.next_f64()
+                            };
+                            priorities.tree.find(mass)
+                        })
+                        .find(|&i| self.is_valid(i) && priorities.tree.get(i) > 0.0);
+                    match found {
+                        Some(i) => {
+                            let probability = priorities.tree.get(i) / total;
+                            weights.push((self.len as f64 * probability).powf(-beta));
+                            self.push_transition(i, 1.0, batch);
+                        },
+                        None => return false,
+                    }
+                }
+
+                let max = weights.iter().cloned().fold(0.0, f64::max);
+                for (weight, out) in weights.iter().zip(batch.weights.iter_mut()) {
+                    *out = (weight / max) as f32;
+                }
+            },
+        }
+
+        true
+    }
+
+    /// Sets the priorities of sampled transitions from the absolute values
+    /// of their TD errors. Does nothing when sampling uniformly.
+    pub fn update_priorities(&mut self, indices: &[usize], td_errors: &[f64]) {
+        assert_eq!(indices.len(), td_errors.len(), "update_priorities needs one TD error per index");
+
+        if let Some(ref mut priorities) = self.priorities {
+            for (&i, &error) in indices.iter().zip(td_errors) {
+                let priority = (error.abs() + priorities.config.epsilon).powf(priorities.config.alpha);
+                priorities.tree.set(i, priority);
+                if priority > priorities.max {
+                    priorities.max = priority;
+                }
+            }
+        }
+    }
+
+    /// Writes the buffer, including its configuration and priorities, in a
+    /// binary format read by `read_from`.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let config = &self.config;
+        try!(w.write_all(MAGIC));
+        try!(write_u8(w, FORMAT_VERSION));
+        try!(write_u64(w, config.capacity as u64));
+        try!(write_u64(w, config.frame_len as u64));
+        try!(write_u64(w, config.stack as u64));
+        try!(write_u64(w, config.n_step as u64));
+        try!(write_f32(w, config.gamma));
+        match config.priority {
+            Some(priority) => {
+                try!(write_bool(w, true));
+                try!(write_f64(w, priority.alpha));
+                try!(write_f64(w, priority.epsilon));
+            },
+            None => try!(write_bool(w, false)),
+        }
+
+        try!(write_u64(w, self.cursor as u64));
+        try!(write_u64(w, self.len as u64));
+        try!(write_bytes(w, &self.frames[..self.len * config.frame_len]));
+        for i in 0..self.len {
+            try!(write_i32(w, self.actions[i].0));
+            try!(write_f32(w, self.rewards[i]));
+            try!(write_bool(w, self.terminals[i]));
+        }
+
+        if let Some(ref priorities) = self.priorities {
+            try!(write_f64(w, priorities.max));
+            for i in 0..self.len {
+                try!(write_f64(w, priorities.tree.get(i)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a buffer written by `write_to`. Only the stored transitions
+    /// are allocated, the rest of the capacity as transitions are added, so
+    /// a corrupt header fails at the end of the input instead of
+    /// allocating.
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        try!(expect_magic(r, MAGIC, "an ALE replay buffer"));
+        let version = try!(read_u8(r));
+        if version != FORMAT_VERSION {
+            return Err(Error::Format(format!("unsupported replay buffer format version {}", version)));
+        }
+
+        let config = ReplayConfig {
+            capacity: try!(read_u64(r)) as usize,
+            frame_len: try!(read_u64(r)) as usize,
+            stack: try!(read_u64(r)) as usize,
+            n_step: try!(read_u64(r)) as usize,
+            gamma: try!(read_f32(r)),
+            priority: if try!(read_bool(r)) {
+                Some(PriorityConfig {
+                    alpha: try!(read_f64(r)),
+                    epsilon: try!(read_f64(r)),
+                })
+            } else {
+                None
+            },
+        };
+        if config.capacity == 0 || config.stack == 0 || config.n_step == 0 {
+            return Err(Error::Format("replay buffer has an empty capacity, stack or n_step".to_owned()));
+        }
+        let cursor = try!(read_u64(r)) as usize;
+        let len = try!(read_u64(r)) as usize;
+        if len > config.capacity || cursor >= config.capacity || (len < config.capacity && cursor != len) {
+            return Err(Error::Format("replay buffer cursor or length out of range".to_owned()));
+        }
+
+        let frames_len = match len.checked_mul(config.frame_len) {
+            Some(frames_len) => frames_len,
+            None => return Err(Error::Format("replay buffer frames are too large".to_owned())),
+        };
+        let frames = try!(read_bytes(r, frames_len));
+        if frames.len() != frames_len {
+            return Err(Error::Format("replay buffer frames have the wrong length".to_owned()));
+        }
+
+        let mut actions = Vec::new();
+        let mut rewards = Vec::new();
+        let mut terminals = Vec::new();
+        for _ in 0..len {
+            actions.push(Action(try!(read_i32(r))));
+            rewards.push(try!(read_f32(r)));
+            terminals.push(try!(read_bool(r)));
+        }
+
+        let priorities = match config.priority {
+            Some(priority) => {
+                let max = try!(read_f64(r));
+                let mut tree = SumTree::new(::std::cmp::max(len, 1));
+                for i in 0..len {
+                    tree.set(i, try!(read_f64(r)));
+                }
+                Some(Priorities { config: priority, tree: tree, max: max })
+            },
+            None => None,
+        };
+
+        Ok(ReplayBuffer {
+            config: config,
+            frames: frames,
+            actions: actions,
+            rewards: rewards,
+            terminals: terminals,
+            cursor: cursor,
+            len: len,
+            priorities: priorities,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::Action;
+    use ::Error;
+    use ::seed::Rng;
+    use super::*;
+
+    fn config(n_step: usize) -> ReplayConfig {
+        ReplayConfig {
+            capacity: 8,
+            frame_len: 2,
+            stack: 2,
+            n_step: n_step,
+            gamma: 0.5,
+            priority: None,
+        }
+    }
+
+    #[test]
+    fn sum_tree_sums_and_finds() {
+        let mut tree = SumTree::new(5);
+        for (i, &value) in [1.0, 0.0, 2.0, 3.0, 4.0].iter().enumerate() {
+            tree.set(i, value);
+        }
+        assert_eq!(tree.total(), 10.0);
+        assert_eq!(tree.get(2), 2.0);
+
+        assert_eq!(tree.find(0.0), 0);
+        assert_eq!(tree.find(0.999), 0);
+        assert_eq!(tree.find(1.0), 2);
+        assert_eq!(tree.find(2.999), 2);
+        assert_eq!(tree.find(3.0), 3);
+        assert_eq!(tree.find(9.999), 4);
+
+        tree.set(4, 0.0);
+        assert_eq!(tree.total(), 6.0);
+    }
+
+    #[test]
+    fn n_step_returns_are_discounted_and_cut_at_terminals() {
+        let mut buffer = ReplayBuffer::new(config(3));
+        buffer.add(&[1, 1], Action(0), 1.0, false);
+        buffer.add(&[2, 2], Action(1), 2.0, false);
+        buffer.add(&[3, 3], Action(2), 4.0, true);
+        buffer.add(&[4, 4], Action(3), 8.0, false);
+
+        let mut batch = Batch::default();
+        buffer.push_transition(0, 1.0, &mut batch);
+        buffer.push_transition(1, 1.0, &mut batch);
+
+        assert_eq!(batch.returns, vec![1.0 + 0.5 * 2.0 + 0.25 * 4.0, 2.0 + 0.5 * 4.0]);
+        assert_eq!(batch.discounts, vec![0.125, 0.25]);
+        assert_eq!(batch.dones, vec![true, true]);
+        assert_eq!(batch.states, vec![0, 0, 1, 1, 1, 1, 2, 2]);
+        assert_eq!(batch.next_states, vec![0; 8]);
+
+        // The return of the first transition after the terminal isn't
+        // complete until two more transitions and a frame are added.
+        assert_eq!(buffer.return_len(3), None);
+        buffer.add(&[5, 5], Action(0), 16.0, false);
+        buffer.add(&[6, 6], Action(0), 32.0, false);
+        assert_eq!(buffer.return_len(3), None);
+        buffer.add(&[7, 7], Action(0), 0.0, false);
+        assert_eq!(buffer.return_len(3), Some((3, false)));
+
+        batch.clear();
+        buffer.push_transition(3, 1.0, &mut batch);
+        assert_eq!(batch.returns, vec![8.0 + 0.5 * 16.0 + 0.25 * 32.0]);
+        // The stack starts at the episode boundary.
+        assert_eq!(batch.states, vec![0, 0, 4, 4]);
+        assert_eq!(batch.next_states, vec![6, 6, 7, 7]);
+    }
+
+    #[test]
+    fn sampling_waits_for_complete_returns() {
+        let mut rng = Rng::new(1);
+        let mut buffer = ReplayBuffer::new(config(2));
+        buffer.add(&[1, 1], Action(0), 1.0, false);
+        assert!(buffer.sample(1, 0.0, &mut rng).is_none());
+
+        buffer.add(&[2, 2], Action(0), 1.0, false);
+        buffer.add(&[3, 3], Action(0), 1.0, false);
+        let batch = buffer.sample(4, 0.0, &mut rng).unwrap();
+        assert_eq!(batch.indices, vec![0; 4]);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut buffer = ReplayBuffer::new(ReplayConfig { priority: Some(PriorityConfig::default()), ..config(1) });
+        for i in 0..10u8 {
+            buffer.add(&[i, i], Action(i as i32), i as f32, i % 4 == 3);
+        }
+        buffer.update_priorities(&[2, 5], &[0.5, 3.0]);
+
+        let mut bytes = Vec::new();
+        buffer.write_to(&mut bytes).unwrap();
+        let read = ReplayBuffer::read_from(&mut &bytes[..]).unwrap();
+
+        assert_eq!(read.config, buffer.config);
+        assert_eq!((read.cursor, read.len), (2, 8));
+        assert_eq!(read.frames, buffer.frames);
+        assert_eq!(read.actions, buffer.actions);
+        assert_eq!(read.rewards, buffer.rewards);
+        assert_eq!(read.terminals, buffer.terminals);
+        let (tree, read_tree) = (&buffer.priorities.as_ref().unwrap().tree, &read.priorities.as_ref().unwrap().tree);
+        assert_eq!(read_tree.total(), tree.total());
+    }
+
+    #[test]
+    fn read_buffers_grow_as_transitions_are_added() {
+        let config = ReplayConfig { priority: Some(PriorityConfig::default()), ..config(1) };
+        let mut buffer = ReplayBuffer::new(config);
+        for i in 0..3u8 {
+            buffer.add(&[i, i], Action(i as i32), i as f32, false);
+        }
+        let mut bytes = Vec::new();
+        buffer.write_to(&mut bytes).unwrap();
+
+        let mut read = ReplayBuffer::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(read.frames.len(), 6);
+        for i in 3..10u8 {
+            buffer.add(&[i, i], Action(i as i32), i as f32, false);
+            read.add(&[i, i], Action(i as i32), i as f32, false);
+        }
+
+        assert_eq!((read.cursor, read.len), (buffer.cursor, buffer.len));
+        assert_eq!(read.frames, buffer.frames);
+        assert_eq!(read.actions, buffer.actions);
+        assert_eq!(read.rewards, buffer.rewards);
+        let (tree, read_tree) = (&buffer.priorities.as_ref().unwrap().tree, &read.priorities.as_ref().unwrap().tree);
+        assert_eq!(read_tree.total(), tree.total());
+    }
+
+    #[test]
+    fn rejects_corrupt_headers() {
+        let mut bytes = Vec::new();
+        ReplayBuffer::new(config(1)).write_to(&mut bytes).unwrap();
+        // capacity, frame_len, stack, n_step, gamma and the priority flag
+        // follow the magic and version, then cursor, len and the frames.
+        let capacity = MAGIC.len() + 1;
+        let cursor = capacity + 37;
+
+        let mut corrupt = bytes.clone();
+        corrupt[capacity..capacity + 8].copy_from_slice(&u64_le(0));
+        match ReplayBuffer::read_from(&mut &corrupt[..]) {
+            Err(Error::Format(_)) => {},
+            other => panic!("expected a format error, got {:?}", other.map(|buffer| buffer.config)),
+        }
+
+        // A huge empty buffer is fine, nothing is allocated for it.
+        let mut huge = bytes.clone();
+        huge[capacity..capacity + 8].copy_from_slice(&u64_le(1 << 40));
+        huge[capacity + 8..capacity + 16].copy_from_slice(&u64_le(1 << 20));
+        let read = ReplayBuffer::read_from(&mut &huge[..]).unwrap();
+        assert_eq!((read.config.capacity, read.len, read.frames.len()), (1 << 40, 0, 0));
+
+        // Lengths the input can't back fail at its end.
+        for &(len, frames_len) in &[(1 << 30, 1 << 50), (1 << 30, 0), (u64::max_value() >> 1, 0)] {
+            let mut corrupt = huge.clone();
+            corrupt[cursor..cursor + 8].copy_from_slice(&u64_le(len));
+            corrupt[cursor + 8..cursor + 16].copy_from_slice(&u64_le(len));
+            corrupt[cursor + 16..cursor + 24].copy_from_slice(&u64_le(frames_len));
+            assert!(ReplayBuffer::read_from(&mut &corrupt[..]).is_err());
+        }
+
+        assert!(ReplayBuffer::read_from(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    fn u64_le(value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_u64(&mut bytes, value).unwrap();
+        bytes
+    }
+}
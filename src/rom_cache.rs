@@ -0,0 +1,226 @@
+//! A content-addressed store for the ROM files of decoded games.
+//!
+//! ALE can only load ROMs from disk, so decoding a game writes its ROM data
+//! back to a file. The cache stores each ROM as `<root>/<md5>/<file name>`:
+//! the directory is named by the content hash, so different ROMs that share
+//! a file name never collide, and the file keeps its original name because
+//! ALE uses it to pick the game's settings.
+use std::env;
+use std::fs::{self,File};
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize,Ordering};
+use std::time::{SystemTime,UNIX_EPOCH};
+use ::Error;
+use ::bytes::file_md5;
+use ::serialize::Rom;
+
+/// The environment variable that overrides the root of `RomCache::from_env`.
+pub const ROM_CACHE_ENV: &'static str = "ALE_ROM_CACHE";
+/// The root used by `RomCache::from_env` when the variable isn't set.
+pub const DEFAULT_ROM_CACHE: &'static str = "./ROMs";
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub struct RomCache {
+    root: PathBuf,
+    temporary: bool,
+}
+
+impl RomCache {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        RomCache {
+            root: root.as_ref().to_path_buf(),
+            temporary: false,
+        }
+    }
+
+    /// A cache rooted at `$ALE_ROM_CACHE`, or `./ROMs` if it isn't set.
+    pub fn from_env() -> Self {
+        match env::var_os(ROM_CACHE_ENV) {
+            Some(root) => RomCache::new(root),
+            None => RomCache::new(DEFAULT_ROM_CACHE),
+        }
+    }
+
+    /// A cache in a fresh directory under the system's temporary directory,
+    /// which is deleted with everything in it when the cache is dropped.
+    pub fn temporary() -> Result<Self, Error> {
+        RomCache::temporary_in(env::temp_dir())
+    }
+
+    /// Like `temporary`, but under the given directory. Passing a memory
+    /// backed file system such as `/dev/shm` keeps the ROMs off the disk.
+    pub fn temporary_in<P: AsRef<Path>>(parent: P) -> Result<Self, Error> {
+        let root = parent.as_ref().join(unique_name("ale-roms"));
+        try!(fs::create_dir_all(&root));
+
+        Ok(RomCache {
+            root: root,
+            temporary: true,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The path a ROM is stored at in this cache.
+    pub fn path_for(&self, rom: &Rom) -> PathBuf {
+        let file_name = rom.rom_path.file_name().expect("Rom didn't have an actual file name?");
+
+        self.root.join(rom.md5()).join(file_name)
+    }
+
+    /// Writes a ROM to the cache unless an identical file is already there,
+    /// and returns its path. A file at that path whose contents don't hash
+    /// to the ROM's MD5 is replaced. Files are written to a temporary name
+    /// and renamed into place, so concurrent readers never see partial ROMs.
+    pub fn store(&self, rom: &Rom) -> Result<PathBuf, Error> {
+        let path = self.path_for(rom);
+
+        if self.contains(rom) {
+            return Ok(path);
+        }
+
+        let dir = path.parent().expect("ROM cache path has no parent").to_path_buf();
+        try!(fs::create_dir_all(&dir));
+
+        let temp_path = dir.join(unique_name(".partial"));
+        {
+            let mut file = try!(File::create(&temp_path));
+            try!(file.write_all(&rom.data));
+            try!(file.sync_all());
+        }
+
+        if let Err(err) = fs::rename(&temp_path, &path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(Error::from(err));
+        }
+
+        Ok(path)
+    }
+
+    /// Whether a verified copy of the ROM is already in the cache.
+    pub fn contains(&self, rom: &Rom) -> bool {
+        let path = self.path_for(rom);
+
+        // A longer file can't be the ROM, so it isn't read.
+        match file_md5(&path, rom.data.len() as u64) {
+            Ok(md5) => md5 == rom.md5(),
+            Err(_) => false,
+        }
+    }
+}
+
+impl Drop for RomCache {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+}
+
+fn unique_name(prefix: &str) -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let count = TEMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+
+    format!("{}-{}-{}-{}", prefix, process::id(), nanos, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self,File};
+    use std::io::{Read,Write};
+    use std::path::{Path,PathBuf};
+    use ::serialize::Rom;
+    use super::*;
+
+    fn rom(data: &[u8]) -> Rom {
+        Rom {
+            rom_path: PathBuf::from("roms/pong.bin"),
+            data: data.to_vec(),
+        }
+    }
+
+    fn read(path: &Path) -> Vec<u8> {
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn stores_by_hash_under_the_file_name() {
+        let cache = RomCache::temporary().unwrap();
+        let pong = rom(b"pong");
+        assert!(!cache.contains(&pong));
+
+        let path = cache.store(&pong).unwrap();
+        assert_eq!(path, cache.root().join(pong.md5()).join("pong.bin"));
+        assert_eq!(read(&path), b"pong");
+        assert!(cache.contains(&pong));
+
+        // No temporary files are left next to it.
+        let names: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(names, vec!["pong.bin"]);
+    }
+
+    #[test]
+    fn different_contents_are_replaced() {
+        let cache = RomCache::temporary().unwrap();
+        let pong = rom(b"pong");
+        let path = cache.path_for(&pong);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path).unwrap().write_all(b"gnop").unwrap();
+        assert!(!cache.contains(&pong));
+
+        assert_eq!(cache.store(&pong).unwrap(), path);
+        assert_eq!(read(&path), b"pong");
+
+        // Longer files, which can't be the ROM, are replaced too.
+        File::create(&path).unwrap().write_all(b"pong and more").unwrap();
+        assert!(!cache.contains(&pong));
+        cache.store(&pong).unwrap();
+        assert_eq!(read(&path), b"pong");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn matching_files_are_reused_and_others_renamed_over() {
+        use std::os::unix::fs::MetadataExt;
+
+        let cache = RomCache::temporary().unwrap();
+        let pong = rom(b"pong");
+        let path = cache.store(&pong).unwrap();
+        let inode = fs::metadata(&path).unwrap().ino();
+
+        cache.store(&pong).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().ino(), inode);
+
+        // A replacement is a new file renamed into place, not a rewrite of
+        // the old one, so readers of the old file never see it change.
+        File::create(&path).unwrap().write_all(b"gnop").unwrap();
+        let mut old = File::open(&path).unwrap();
+        cache.store(&pong).unwrap();
+        assert!(fs::metadata(&path).unwrap().ino() != inode);
+        let mut data = Vec::new();
+        old.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"gnop");
+    }
+
+    #[test]
+    fn only_temporary_caches_are_removed_on_drop() {
+        let cache = RomCache::temporary().unwrap();
+        let root = cache.root().to_path_buf();
+        cache.store(&rom(b"pong")).unwrap();
+        drop(cache);
+        assert!(!root.exists());
+
+        let parent = RomCache::temporary().unwrap();
+        let cache = RomCache::new(parent.root().join("kept"));
+        let path = cache.store(&rom(b"pong")).unwrap();
+        drop(cache);
+        assert!(path.exists());
+    }
+}
@@ -0,0 +1,214 @@
+//! Deterministic seeding from a single master seed.
+//!
+//! Seeds are derived with the SplitMix64 finalizer, which is cheap and maps
+//! nearby inputs to unrelated outputs. The scheme is fixed so that a recorded
+//! (master seed, environment id, episode) triple always reproduces the same
+//! episode:
+//!
+//! ```text
+//! env_seed     = mix(master   + GAMMA * (env_id  + 1))
+//! episode_seed = mix(env_seed + GAMMA * (episode + 1))
+//! stream_seed  = mix(seed ^ mix(stream))
+//! ```
+//!
+//! where `GAMMA = 0x9E3779B97F4A7C15` and all arithmetic wraps. Stream seeds
+//! give wrappers (sticky actions, no-op starts, ...) their own generator per
+//! episode without consuming ALE's.
+
+const GAMMA: u64 = 0x9E3779B97F4A7C15;
+
+/// Well-known stream ids for wrapper RNGs, used with `stream_seed`, see
+/// `StickyActions` and the `noop` module.
+pub const STICKY_ACTIONS_STREAM: u64 = 1;
+pub const NOOP_STARTS_STREAM: u64 = 2;
+
+fn mix(z: u64) -> u64 {
+    let z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// A master seed from which per-environment and per-episode seeds are split.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub struct SeedSequence {
+    master: u64,
+}
+
+impl SeedSequence {
+    pub fn new(master: u64) -> Self {
+        SeedSequence { master: master }
+    }
+
+    pub fn master(&self) -> u64 {
+        self.master
+    }
+
+    /// The seed of the environment with the given id.
+    pub fn env_seed(&self, env_id: u64) -> u64 {
+        mix(self.master.wrapping_add(GAMMA.wrapping_mul(env_id.wrapping_add(1))))
+    }
+
+    /// The seed of the given episode (counting from 0) of an environment.
+    pub fn episode_seed(&self, env_id: u64, episode: u64) -> u64 {
+        let env = self.env_seed(env_id);
+        mix(env.wrapping_add(GAMMA.wrapping_mul(episode.wrapping_add(1))))
+    }
+}
+
+/// The seed of one episode of one environment, together with everything
+/// needed to derive it again.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub struct EpisodeSeed {
+    pub master: u64,
+    pub env_id: u64,
+    pub episode: u64,
+    pub seed: u64,
+}
+
+impl EpisodeSeed {
+    pub fn new(master: u64, env_id: u64, episode: u64) -> Self {
+        EpisodeSeed {
+            master: master,
+            env_id: env_id,
+            episode: episode,
+            seed: SeedSequence::new(master).episode_seed(env_id, episode),
+        }
+    }
+
+    /// The seed of the following episode of the same environment.
+    pub fn next_episode(&self) -> Self {
+        EpisodeSeed::new(self.master, self.env_id, self.episode.wrapping_add(1))
+    }
+
+    /// The seed of the given wrapper stream for this episode.
+    pub fn stream(&self, stream: u64) -> u64 {
+        stream_seed(self.seed, stream)
+    }
+}
+
+/// Derives the seed of an independent stream (see the `*_STREAM` constants)
+/// from an episode seed.
+pub fn stream_seed(seed: u64, stream: u64) -> u64 {
+    mix(seed ^ mix(stream))
+}
+
+/// Converts a 64-bit seed to the non-negative value ALE accepts for its
+/// `random_seed` setting.
+pub fn ale_seed(seed: u64) -> i32 {
+    (seed >> 33) as i32
+}
+
+/// A small SplitMix64 generator. Its output is fixed by this crate rather
+/// than by a dependency, so recorded seeds stay valid across upgrades.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GAMMA);
+        mix(self.state)
+    }
+
+    /// A float uniformly distributed in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer uniformly distributed in `[0, n)`. Panics if `n` is 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "Rng::below called with an empty range");
+        let zone = u64::max_value() - u64::max_value() % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rng_matches_splitmix64() {
+        // The first outputs of the reference SplitMix64 seeded with 0.
+        let mut rng = Rng::new(0);
+        assert_eq!(rng.next_u64(), 0xE220A8397B1DCDAF);
+        assert_eq!(rng.next_u64(), 0x6E789E6AA1B965F4);
+        assert_eq!(rng.next_u64(), 0x06C45D188009454F);
+    }
+
+    #[test]
+    fn seeds_are_reproducible() {
+        let seeds = SeedSequence::new(42);
+        assert_eq!(seeds.episode_seed(3, 5), SeedSequence::new(42).episode_seed(3, 5));
+        assert_eq!(EpisodeSeed::new(42, 3, 5).seed, seeds.episode_seed(3, 5));
+        assert_eq!(EpisodeSeed::new(42, 3, 5).next_episode(), EpisodeSeed::new(42, 3, 6));
+    }
+
+    #[test]
+    fn nearby_inputs_give_unrelated_seeds() {
+        let mut seen = Vec::new();
+        for master in 0..4 {
+            let seeds = SeedSequence::new(master);
+            for env_id in 0..8 {
+                seen.push(seeds.env_seed(env_id));
+                for episode in 0..8 {
+                    seen.push(seeds.episode_seed(env_id, episode));
+                }
+            }
+        }
+
+        let count = seen.len();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), count);
+
+        // Consecutive environments differ in about half of their bits.
+        let seeds = SeedSequence::new(0);
+        let flipped = (seeds.env_seed(0) ^ seeds.env_seed(1)).count_ones();
+        assert!(flipped > 16 && flipped < 48, "only {} bits differ", flipped);
+    }
+
+    #[test]
+    fn streams_are_independent() {
+        let episode = EpisodeSeed::new(1, 2, 3);
+        let sticky = episode.stream(STICKY_ACTIONS_STREAM);
+        let noop = episode.stream(NOOP_STARTS_STREAM);
+
+        assert!(sticky != noop);
+        assert!(sticky != episode.seed && noop != episode.seed);
+        assert_eq!(sticky, stream_seed(episode.seed, STICKY_ACTIONS_STREAM));
+
+        let mut a = Rng::new(sticky);
+        let mut b = Rng::new(noop);
+        let same = (0..64).filter(|_| a.next_u64() == b.next_u64()).count();
+        assert_eq!(same, 0);
+    }
+
+    #[test]
+    fn ale_seeds_are_non_negative() {
+        for &seed in &[0, 1, u64::max_value(), 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF] {
+            assert!(ale_seed(seed) >= 0);
+        }
+    }
+
+    #[test]
+    fn rng_ranges() {
+        let mut rng = Rng::new(9);
+        for _ in 0..1000 {
+            let x = rng.next_f64();
+            assert!(x >= 0.0 && x < 1.0);
+            assert!(rng.below(7) < 7);
+        }
+        assert_eq!(Rng::new(1).below(1), 0);
+    }
+}
@@ -0,0 +1,136 @@
+//! Snapshots of the ALE settings that affect emulation.
+use std::io::{self,Read,Write};
+use ::{Ale,Error};
+use ::bytes::*;
+
+/// The values of the ALE settings this crate knows about. Most of them are
+/// only read by ALE when a ROM is loaded, so `apply` should be called before
+/// `Ale::load_rom`.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct Settings {
+    pub random_seed: i32,
+    pub frame_skip: i32,
+    pub max_num_frames: i32,
+    pub max_num_frames_per_episode: i32,
+    pub repeat_action_probability: f32,
+    pub color_averaging: bool,
+    pub display_screen: bool,
+    pub sound: bool,
+    pub record_screen_dir: String,
+}
+
+#[cfg(feature="rustc-serialize")]
+rustc_serialize_struct!(Settings {
+    random_seed, frame_skip, max_num_frames, max_num_frames_per_episode, repeat_action_probability,
+    color_averaging, display_screen, sound, record_screen_dir
+});
+
+impl Settings {
+    /// Reads the current settings of an ALE instance.
+    pub fn capture(ale: &Ale) -> Self {
+        Settings {
+            random_seed: ale.get_int("random_seed"),
+            frame_skip: ale.get_int("frame_skip"),
+            max_num_frames: ale.get_int("max_num_frames"),
+            max_num_frames_per_episode: ale.get_int("max_num_frames_per_episode"),
+            repeat_action_probability: ale.get_float("repeat_action_probability"),
+            color_averaging: ale.get_bool("color_averaging"),
+            display_screen: ale.get_bool("display_screen"),
+            sound: ale.get_bool("sound"),
+            record_screen_dir: ale.get_string("record_screen_dir").to_owned(),
+        }
+    }
+
+    /// Sets every setting in the snapshot on an ALE instance.
+    pub fn apply(&self, ale: &mut Ale) {
+        self.apply_deterministic(ale);
+        ale.set_bool("display_screen", self.display_screen);
+        ale.set_bool("sound", self.sound);
+        ale.set_string("record_screen_dir", &self.record_screen_dir);
+    }
+
+    /// Sets only the settings that affect determinism, leaving display and
+    /// recording settings such as display_screen to the caller.
+    pub fn apply_deterministic(&self, ale: &mut Ale) {
+        ale.set_int("random_seed", self.random_seed);
+        ale.set_int("frame_skip", self.frame_skip);
+        ale.set_int("max_num_frames", self.max_num_frames);
+        ale.set_int("max_num_frames_per_episode", self.max_num_frames_per_episode);
+        ale.set_float("repeat_action_probability", self.repeat_action_probability);
+        ale.set_bool("color_averaging", self.color_averaging);
+    }
+
+    /// Writes the settings in field order, in the little-endian encoding
+    /// of the crate's binary formats.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        try!(write_i32(w, self.random_seed));
+        try!(write_i32(w, self.frame_skip));
+        try!(write_i32(w, self.max_num_frames));
+        try!(write_i32(w, self.max_num_frames_per_episode));
+        try!(write_f32(w, self.repeat_action_probability));
+        try!(write_bool(w, self.color_averaging));
+        try!(write_bool(w, self.display_screen));
+        try!(write_bool(w, self.sound));
+        write_str(w, &self.record_screen_dir)
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        Ok(Settings {
+            random_seed: try!(read_i32(r)),
+            frame_skip: try!(read_i32(r)),
+            max_num_frames: try!(read_i32(r)),
+            max_num_frames_per_episode: try!(read_i32(r)),
+            repeat_action_probability: try!(read_f32(r)),
+            color_averaging: try!(read_bool(r)),
+            display_screen: try!(read_bool(r)),
+            sound: try!(read_bool(r)),
+            record_screen_dir: try!(read_str(r, 4096)),
+        })
+    }
+
+    /// The setting keys and their values, formatted for display.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("random_seed", self.random_seed.to_string()),
+            ("frame_skip", self.frame_skip.to_string()),
+            ("max_num_frames", self.max_num_frames.to_string()),
+            ("max_num_frames_per_episode", self.max_num_frames_per_episode.to_string()),
+            ("repeat_action_probability", self.repeat_action_probability.to_string()),
+            ("color_averaging", self.color_averaging.to_string()),
+            ("display_screen", self.display_screen.to_string()),
+            ("sound", self.sound.to_string()),
+            ("record_screen_dir", self.record_screen_dir.clone()),
+        ]
+    }
+
+    /// The settings whose values differ between `self` and `other`.
+    pub fn diff(&self, other: &Settings) -> Vec<SettingChange> {
+        self.entries().into_iter().zip(other.entries()).filter(|&(ref a, ref b)| a.1 != b.1)
+            .map(|((key, from), (_, to))| SettingChange { key: key, from: from, to: to })
+            .collect()
+    }
+}
+
+/// A setting that differs between two snapshots.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SettingChange {
+    pub key: &'static str,
+    pub from: String,
+    pub to: String,
+}
+
+impl SettingChange {
+    /// Whether changing this setting changes what the emulator does or
+    /// observes, as opposed to only how it is displayed or recorded.
+    pub fn affects_determinism(&self) -> bool {
+        affects_determinism(self.key)
+    }
+}
+
+pub fn affects_determinism(key: &str) -> bool {
+    match key {
+        "display_screen" | "sound" | "record_screen_dir" => false,
+        _ => true,
+    }
+}
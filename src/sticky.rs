@@ -0,0 +1,227 @@
+//! Sticky actions implemented in Rust rather than inside ALE.
+//!
+//! ALE's `repeat_action_probability` draws from the C library's own RNG, so
+//! which actions were repeated can't be seen or replayed from Rust. The
+//! `StickyActions` wrapper draws from a `seed::Rng` instead and reports every
+//! repeated action in `StepInfo::sticky`.
+//!
+//! Load the game with `repeat_action_probability` set to 0.0, otherwise
+//! actions are made sticky twice. Note that ALE repeats actions per frame
+//! while this wrapper repeats them per call to `step`.
+use std::ops::Deref;
+use ::{Action,Env,Game,StepInfo};
+use ::seed::{self,EpisodeSeed,Rng};
+
+pub struct StickyActions {
+    game: Game,
+    sticky: Sticky,
+}
+
+/// Decides which steps repeat the previous action, apart from the game.
+struct Sticky {
+    probability: f64,
+    enabled: bool,
+    rng: Rng,
+    last_action: Action,
+}
+
+impl Sticky {
+    fn new(probability: f64, rng: Rng) -> Self {
+        Sticky {
+            probability: probability,
+            enabled: true,
+            rng: rng,
+            last_action: Action(0),
+        }
+    }
+
+    /// The action to perform instead of `action`, and whether it stuck.
+    fn choose(&mut self, action: Action) -> (Action, bool) {
+        let sticky = self.enabled && self.rng.next_f64() < self.probability;
+        let action = if sticky { self.last_action } else { action };
+        self.last_action = action;
+
+        (action, sticky)
+    }
+
+    /// Forgets the previous action and, for seeded games, reseeds the RNG
+    /// for the new episode.
+    fn reset(&mut self, seed: Option<EpisodeSeed>) {
+        self.last_action = Action(0);
+        if let Some(episode) = seed {
+            self.rng = Rng::new(episode.stream(seed::STICKY_ACTIONS_STREAM));
+        }
+    }
+}
+
+impl StickyActions {
+    /// Wraps an unseeded game, repeating the previous action with the given
+    /// probability and drawing from an RNG seeded with `seed`.
+    ///
+    /// Panics if the game was loaded with `Ale::load_rom_seeded`, since its
+    /// episode seeds already determine the RNG. Use `seeded` for those.
+    pub fn new(game: Game, probability: f64, seed: u64) -> Self {
+        assert!(game.seed().is_none(), "StickyActions::new called with a seeded game, use StickyActions::seeded");
+
+        StickyActions::with_rng(game, probability, Rng::new(seed))
+    }
+
+    /// Wraps a game loaded with `Ale::load_rom_seeded`, seeding the RNG from
+    /// the sticky actions stream of the episode seed and reseeding it on
+    /// every reset.
+    ///
+    /// Panics if the game is not seeded.
+    pub fn seeded(game: Game, probability: f64) -> Self {
+        let rng = match game.seed() {
+            Some(episode) => Rng::new(episode.stream(seed::STICKY_ACTIONS_STREAM)),
+            None => panic!("StickyActions::seeded called with an unseeded game, use StickyActions::new"),
+        };
+
+        StickyActions::with_rng(game, probability, rng)
+    }
+
+    fn with_rng(game: Game, probability: f64, rng: Rng) -> Self {
+        StickyActions {
+            game: game,
+            sticky: Sticky::new(probability, rng),
+        }
+    }
+
+    /// Performs the given action, or the previous one if it sticks.
+    pub fn step(&mut self, action: Action) -> StepInfo {
+        let (action, sticky) = self.sticky.choose(action);
+
+        let mut info = self.game.step(action);
+        info.sticky = sticky;
+
+        info
+    }
+
+    /// Resets the game and forgets the previous action. Seeded games move
+    /// the RNG on to the new episode's seed.
+    pub fn reset(&mut self) {
+        self.game.reset();
+        self.sticky.reset(self.game.seed());
+    }
+
+    pub fn probability(&self) -> f64 {
+        self.sticky.probability
+    }
+
+    /// Changes the repeat probability. Intended to be called between
+    /// episodes.
+    pub fn set_probability(&mut self, probability: f64) {
+        self.sticky.probability = probability;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sticky.enabled
+    }
+
+    /// Turns stickiness on or off. While off, the RNG is not advanced.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.sticky.enabled = enabled;
+    }
+
+    /// Replaces the RNG seed of an unseeded game.
+    ///
+    /// Panics if the game is seeded, since its RNG follows the episode seeds.
+    pub fn reseed(&mut self, seed: u64) {
+        assert!(self.game.seed().is_none(), "StickyActions::reseed called with a seeded game");
+        self.sticky.rng = Rng::new(seed);
+    }
+
+    pub fn into_inner(self) -> Game {
+        self.game
+    }
+}
+
+impl Env for StickyActions {
+    fn step(&mut self, action: Action) -> StepInfo {
+        StickyActions::step(self, action)
+    }
+
+    fn reset(&mut self) {
+        StickyActions::reset(self)
+    }
+
+    fn game(&self) -> &Game {
+        &self.game
+    }
+}
+
+impl Deref for StickyActions {
+    type Target=Game;
+
+    fn deref(&self) -> &Game {
+        &self.game
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::Action;
+    use ::seed::{self,EpisodeSeed,Rng};
+    use super::Sticky;
+
+    fn run(sticky: &mut Sticky, steps: i32) -> Vec<(Action, bool)> {
+        (1..steps + 1).map(|i| sticky.choose(Action(i))).collect()
+    }
+
+    #[test]
+    fn decisions_are_reproducible() {
+        let first = run(&mut Sticky::new(0.25, Rng::new(5)), 200);
+        assert_eq!(run(&mut Sticky::new(0.25, Rng::new(5)), 200), first);
+        assert!(first.iter().any(|&(_, sticky)| sticky));
+        assert!(first.iter().any(|&(_, sticky)| !sticky));
+        assert!(run(&mut Sticky::new(0.25, Rng::new(6)), 200) != first);
+
+        // A sticky step performs whatever was performed before it.
+        let mut last = Action(0);
+        for (i, &(action, sticky)) in first.iter().enumerate() {
+            assert_eq!(action, if sticky { last } else { Action(i as i32 + 1) });
+            last = action;
+        }
+    }
+
+    #[test]
+    fn disabling_doesnt_advance_the_rng() {
+        let mut paused = Sticky::new(0.5, Rng::new(3));
+        paused.enabled = false;
+        assert!(run(&mut paused, 50).iter().all(|&(_, sticky)| !sticky));
+        paused.enabled = true;
+
+        let mut fresh = Sticky::new(0.5, Rng::new(3));
+        let decisions = |steps: Vec<(Action, bool)>| steps.into_iter().map(|(_, sticky)| sticky).collect::<Vec<_>>();
+        assert_eq!(decisions(run(&mut paused, 100)), decisions(run(&mut fresh, 100)));
+    }
+
+    #[test]
+    fn reset_reseeds_from_the_episode_stream() {
+        let episode = EpisodeSeed::new(7, 1, 4);
+        let mut sticky = Sticky::new(0.5, Rng::new(99));
+        run(&mut sticky, 10);
+        sticky.reset(Some(episode));
+        assert_eq!(sticky.last_action, Action(0));
+
+        let mut expected = Rng::new(episode.stream(seed::STICKY_ACTIONS_STREAM));
+        for _ in 0..20 {
+            assert_eq!(sticky.rng.next_u64(), expected.next_u64());
+        }
+
+        // Unseeded games keep their RNG.
+        let mut unseeded = Sticky::new(0.5, Rng::new(99));
+        run(&mut unseeded, 10);
+        let mut expected = unseeded.rng.clone();
+        unseeded.reset(None);
+        assert_eq!(unseeded.rng.next_u64(), expected.next_u64());
+    }
+
+    #[test]
+    fn probabilities_at_the_bounds() {
+        assert!(run(&mut Sticky::new(0.0, Rng::new(1)), 1000).iter().all(|&(_, sticky)| !sticky));
+
+        let always = run(&mut Sticky::new(1.0, Rng::new(1)), 1000);
+        assert!(always.iter().all(|&(action, sticky)| sticky && action == Action(0)));
+    }
+}
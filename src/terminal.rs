@@ -0,0 +1,375 @@
+//! Drawing game screens in a terminal, for machines without a display.
+//!
+//! `TerminalRenderer` draws `Game::screen_rgb` either with ANSI truecolor
+//! half-block characters, two pixels per character cell, or as sixel
+//! graphics for terminals that support them. Frames are downscaled to fit
+//! the terminal and followed by an overlay line. Each frame is drawn over the
+//! previous one, with lines ending in `\r\n` so they also draw correctly in
+//! raw mode. A loop like
+//!
+//! ```text
+//! let mut renderer = TerminalRenderer::new(RenderMode::HalfBlocks);
+//! while !game.is_over() {
+//!     reward += game.act(action);
+//!     try!(renderer.draw(&mut stdout, &game, reward));
+//! }
+//! try!(renderer.restore(&mut stdout));
+//! ```
+//!
+//! shows the game as it plays.
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{self,Write};
+use std::process::{Command,Stdio};
+use ::Game;
+use ::video::{ColorType,index_colors};
+
+/// ALE pixels are displayed twice as wide as they are tall.
+const PIXEL_ASPECT: usize = 2;
+
+/// The assumed size of a character cell in pixels, for fitting sixel
+/// graphics. Terminals don't report it portably.
+const SIXEL_CELL: (usize, usize) = (8, 16);
+
+/// The size of the controlling terminal in columns and rows, from the
+/// `COLUMNS` and `LINES` variables or else `stty size`.
+pub fn terminal_size() -> Option<(u32, u32)> {
+    let var = |key: &str| env::var(key).ok().and_then(|v| v.trim().parse::<u32>().ok()).and_then(|v| if v > 0 { Some(v) } else { None });
+    if let (Some(columns), Some(lines)) = (var("COLUMNS"), var("LINES")) {
+        return Some((columns, lines));
+    }
+
+    let tty = match File::open("/dev/tty") {
+        Ok(tty) => tty,
+        Err(_) => return None,
+    };
+    let output = match Command::new("stty").arg("size").stdin(tty).stderr(Stdio::null()).output() {
+        Ok(ref output) if output.status.success() => String::from_utf8_lossy(&output.stdout).into_owned(),
+        _ => return None,
+    };
+
+    let mut fields = output.split_whitespace().map(|field| field.parse::<u32>().ok());
+    match (fields.next(), fields.next()) {
+        (Some(Some(rows)), Some(Some(columns))) if rows > 0 && columns > 0 => Some((columns, rows)),
+        _ => None,
+    }
+}
+
+/// Averages each block of source pixels into one pixel of the target size.
+fn resize(rgb: &[u8], width: usize, height: usize, target_width: usize, target_height: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(target_width * target_height * 3);
+    for ty in 0..target_height {
+        let y0 = ty * height / target_height;
+        let y1 = ::std::cmp::max(y0 + 1, (ty + 1) * height / target_height);
+        for tx in 0..target_width {
+            let x0 = tx * width / target_width;
+            let x1 = ::std::cmp::max(x0 + 1, (tx + 1) * width / target_width);
+
+            let mut sum = [0usize; 3];
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let i = 3 * (y * width + x);
+                    sum[0] += rgb[i] as usize;
+                    sum[1] += rgb[i + 1] as usize;
+                    sum[2] += rgb[i + 2] as usize;
+                }
+            }
+            let n = (y1 - y0) * (x1 - x0);
+            out.extend_from_slice(&[(sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8]);
+        }
+    }
+
+    out
+}
+
+/// The largest size no bigger than `width` x `height` with the frame's
+/// displayed aspect ratio that fits in `max_width` x `max_height`.
+fn fit(width: usize, height: usize, max_width: usize, max_height: usize) -> (usize, usize) {
+    let display_width = width * PIXEL_ASPECT;
+    if display_width <= max_width && height <= max_height {
+        return (display_width, height);
+    }
+
+    if max_width * height <= max_height * display_width {
+        (max_width, ::std::cmp::max(1, max_width * height / display_width))
+    } else {
+        (::std::cmp::max(1, max_height * display_width / height), max_height)
+    }
+}
+
+/// The text under each frame.
+pub fn overlay(reward: i32, lives: i32, frame_number: i32) -> String {
+    format!("reward {}  lives {}  frame {}", reward, lives, frame_number)
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum RenderMode {
+    /// ANSI truecolor upper half blocks, supported by most terminals.
+    HalfBlocks,
+    /// Sixel graphics, at a higher resolution in terminals that support it.
+    Sixel,
+}
+
+pub struct TerminalRenderer {
+    mode: RenderMode,
+    size: Option<(u32, u32)>,
+    started: bool,
+    buf: Vec<u8>,
+}
+
+impl TerminalRenderer {
+    /// Creates a renderer that fits frames to the terminal's size, as
+    /// detected when the first frame is rendered.
+    pub fn new(mode: RenderMode) -> Self {
+        TerminalRenderer {
+            mode: mode,
+            size: None,
+            started: false,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Fits frames to the given number of columns and rows instead of the
+    /// terminal's size.
+    pub fn set_size(&mut self, columns: u32, rows: u32) {
+        self.size = Some((columns, rows));
+    }
+
+    /// Detects the terminal's size again, e.g. after it was resized.
+    pub fn refresh_size(&mut self) {
+        self.size = Some(terminal_size().unwrap_or((80, 24)));
+    }
+
+    fn size(&mut self) -> (usize, usize) {
+        if self.size.is_none() {
+            self.refresh_size();
+        }
+        let (columns, rows) = self.size.unwrap();
+
+        (columns as usize, rows as usize)
+    }
+
+    /// Renders an RGB frame followed by an overlay line, ready to be
+    /// written to the terminal.
+    pub fn render(&mut self, rgb: &[u8], width: usize, height: usize, overlay: &str) -> String {
+        assert_eq!(rgb.len(), width * height * 3, "frame doesn't match its dimensions");
+        let (columns, rows) = self.size();
+        // One row is left for the overlay, and one more so the terminal
+        // doesn't scroll after it.
+        let rows = if rows > 2 { rows - 2 } else { 1 };
+
+        let mut out = String::new();
+        if !self.started {
+            // Clear the screen and hide the cursor.
+            out.push_str("\x1b[2J\x1b[?25l");
+            self.started = true;
+        }
+        out.push_str("\x1b[H");
+
+        match self.mode {
+            RenderMode::HalfBlocks => {
+                let (w, h) = fit(width, height, columns, 2 * rows);
+                half_blocks(&resize(rgb, width, height, w, h), w, h, &mut out);
+            },
+            RenderMode::Sixel => {
+                let (w, h) = fit(width, height, columns * SIXEL_CELL.0, rows * SIXEL_CELL.1);
+                sixel(&resize(rgb, width, height, w, h), w, h, &mut out);
+            },
+        }
+
+        out.push_str("\x1b[0m");
+        out.extend(overlay.chars().take(columns));
+        out.push_str("\x1b[K\r\n");
+
+        out
+    }
+
+    /// Draws the game's screen with an overlay of the given reward, e.g.
+    /// the return so far, and the game's lives and frame number.
+    pub fn draw<W: Write>(&mut self, w: &mut W, game: &Game, reward: i32) -> io::Result<()> {
+        let mut buf = ::std::mem::take(&mut self.buf);
+        game.screen_rgb_in_buf(&mut buf);
+        let (width, height) = game.screen_dimensions();
+        let text = overlay(reward, game.lives(), game.frame_number());
+        let frame = self.render(&buf, width as usize, height as usize, &text);
+        self.buf = buf;
+
+        try!(w.write_all(frame.as_bytes()));
+        w.flush()
+    }
+
+    /// Resets colors and shows the cursor again.
+    pub fn restore<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        self.started = false;
+        try!(w.write_all(b"\x1b[0m\x1b[?25h"));
+        w.flush()
+    }
+}
+
+/// Draws two rows of pixels per line, the upper one as the foreground of
+/// `▀` and the lower one as its background.
+fn half_blocks(rgb: &[u8], width: usize, height: usize, out: &mut String) {
+    let pixel = |x: usize, y: usize| {
+        if y < height {
+            let i = 3 * (y * width + x);
+            (rgb[i], rgb[i + 1], rgb[i + 2])
+        } else {
+            (0, 0, 0)
+        }
+    };
+
+    for y in (0..height).step_by(2) {
+        let mut last = None;
+        for x in 0..width {
+            let colors = (pixel(x, y), pixel(x, y + 1));
+            if last != Some(colors) {
+                let ((r, g, b), (br, bg, bb)) = colors;
+                let _ = write!(out, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m", r, g, b, br, bg, bb);
+                last = Some(colors);
+            }
+            out.push('\u{2580}');
+        }
+        out.push_str("\x1b[0m\x1b[K\r\n");
+    }
+}
+
+/// Encodes a frame as sixels, six rows of pixels per band, one pass per
+/// color in each band, with runs of equal columns compressed.
+fn sixel(rgb: &[u8], width: usize, height: usize, out: &mut String) {
+    let (table, indices) = index_colors(ColorType::Rgb, rgb);
+
+    let _ = write!(out, "\x1bPq\"1;1;{};{}", width, height);
+    let mut defined = [false; 256];
+    for &index in &indices {
+        if !defined[index as usize] {
+            defined[index as usize] = true;
+            let i = 3 * index as usize;
+            let _ = write!(out, "#{};2;{};{};{}", index,
+                           table[i] as u32 * 100 / 255, table[i + 1] as u32 * 100 / 255, table[i + 2] as u32 * 100 / 255);
+        }
+    }
+
+    let mut row = vec![0u8; width];
+    for band in (0..height).step_by(6) {
+        let band_end = ::std::cmp::min(band + 6, height);
+        let mut in_band = [false; 256];
+        for y in band..band_end {
+            for &index in &indices[y * width..(y + 1) * width] {
+                in_band[index as usize] = true;
+            }
+        }
+
+        let mut first = true;
+        for color in (0..256).filter(|&c| in_band[c]) {
+            for (x, bits) in row.iter_mut().enumerate() {
+                *bits = 0;
+                for y in band..band_end {
+                    if indices[y * width + x] as usize == color {
+                        *bits |= 1 << (y - band);
+                    }
+                }
+            }
+
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            let _ = write!(out, "#{}", color);
+
+            let mut x = 0;
+            while x < width {
+                let run = row[x..].iter().take_while(|&&bits| bits == row[x]).count();
+                let c = (63 + row[x]) as char;
+                if run > 3 {
+                    let _ = write!(out, "!{}{}", run, c);
+                } else {
+                    for _ in 0..run {
+                        out.push(c);
+                    }
+                }
+                x += run;
+            }
+        }
+        out.push('-');
+    }
+
+    out.push_str("\x1b\\\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_keeps_the_displayed_aspect_ratio() {
+        // Small enough frames are only widened.
+        assert_eq!(fit(160, 210, 400, 300), (320, 210));
+        // Limited by the width, then by the height.
+        assert_eq!(fit(160, 210, 80, 100), (80, 52));
+        assert_eq!(fit(160, 210, 200, 42), (64, 42));
+        assert_eq!(fit(160, 210, 1, 1), (1, 1));
+        assert_eq!(fit(160, 210, 1000, 1), (1, 1));
+    }
+
+    #[test]
+    fn resize_averages_blocks() {
+        let rgb = [0, 0, 0, 100, 100, 100, 200, 200, 200, 50, 60, 70];
+        assert_eq!(resize(&rgb, 2, 2, 1, 1), vec![87, 90, 92]);
+        assert_eq!(resize(&rgb, 2, 2, 1, 2), vec![50, 50, 50, 125, 130, 135]);
+        assert_eq!(resize(&rgb, 2, 2, 2, 2), rgb.to_vec());
+        // Upscaling repeats pixels.
+        assert_eq!(resize(&rgb[..3 * 2], 2, 1, 4, 1), vec![0, 0, 0, 0, 0, 0, 100, 100, 100, 100, 100, 100]);
+    }
+
+    #[test]
+    fn half_blocks_pair_rows_and_skip_repeated_colors() {
+        let rgb = [1, 2, 3, 1, 2, 3, 4, 5, 6, 4, 5, 6, 7, 8, 9, 0, 0, 0];
+        let mut out = String::new();
+        half_blocks(&rgb, 2, 3, &mut out);
+
+        assert_eq!(out, "\x1b[38;2;1;2;3m\x1b[48;2;4;5;6m\u{2580}\u{2580}\x1b[0m\x1b[K\r\n\
+                         \x1b[38;2;7;8;9m\x1b[48;2;0;0;0m\u{2580}\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m\u{2580}\x1b[0m\x1b[K\r\n");
+    }
+
+    #[test]
+    fn sixel_bands_hold_six_rows() {
+        // A 1x7 column: six red pixels, then a blue one in a second band.
+        let mut rgb = Vec::new();
+        for _ in 0..6 {
+            rgb.extend_from_slice(&[255, 0, 0]);
+        }
+        rgb.extend_from_slice(&[0, 0, 255]);
+        let mut out = String::new();
+        sixel(&rgb, 1, 7, &mut out);
+
+        assert_eq!(out, "\x1bPq\"1;1;1;7#0;2;100;0;0#1;2;0;0;100#0~-#1@-\x1b\\\r\n");
+    }
+
+    #[test]
+    fn sixel_compresses_runs() {
+        let rgb = vec![0u8; 3 * 5];
+        let mut out = String::new();
+        sixel(&rgb, 5, 1, &mut out);
+
+        assert!(out.contains("#0!5@-"));
+    }
+
+    #[test]
+    fn render_fits_the_given_size() {
+        let mut renderer = TerminalRenderer::new(RenderMode::HalfBlocks);
+        renderer.set_size(4, 4);
+        let rgb = vec![10u8; 4 * 4 * 3];
+
+        let first = renderer.render(&rgb, 4, 4, "reward 1");
+        assert!(first.starts_with("\x1b[2J\x1b[?25l\x1b[H"));
+        // Two rows are left for the frame, so it is 4 cells wide and 1 high,
+        // and the overlay is cut to the width.
+        assert_eq!(first.matches('\u{2580}').count(), 4);
+        assert_eq!(first.matches("\r\n").count(), 2);
+        assert!(first.ends_with("\x1b[0mrewa\x1b[K\r\n"));
+
+        let second = renderer.render(&rgb, 4, 4, "");
+        assert!(second.starts_with("\x1b[H"));
+    }
+}
@@ -0,0 +1,310 @@
+//! A small zlib encoder: greedy LZ77 matching with the fixed Huffman codes of
+//! deflate. It compresses Atari screens well, which are mostly long runs and
+//! repeated rows, without a dependency on a compression library.
+
+const WINDOW: usize = 1 << 15;
+const HASH_BITS: usize = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions with the same hash are tried per match.
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new(out: Vec<u8>) -> Self {
+        BitWriter { out: out, acc: 0, bits: 0 }
+    }
+
+    /// Writes the low `n` bits of `value`, least significant bit first.
+    fn write(&mut self, value: u32, n: u32) {
+        self.acc |= (value as u64) << self.bits;
+        self.bits += n;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which deflate stores most significant bit first.
+    fn write_code(&mut self, code: u32, n: u32) {
+        let mut reversed = 0;
+        for i in 0..n {
+            reversed |= ((code >> i) & 1) << (n - 1 - i);
+        }
+        self.write(reversed, n);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn write_literal(w: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => w.write_code(0x30 + symbol, 8),
+        144..=255 => w.write_code(0x190 + symbol - 144, 9),
+        256..=279 => w.write_code(symbol - 256, 7),
+        _ => w.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap();
+    write_literal(w, 257 + code as u32);
+    w.write((len - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+
+    let code = DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap();
+    w.write_code(code as u32, 5);
+    w.write((dist - DIST_BASE[code] as usize) as u32, DIST_EXTRA[code] as u32);
+}
+
+fn hash(data: &[u8]) -> usize {
+    let v = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn insert(data: &[u8], head: &mut [usize], prev: &mut [usize], pos: usize) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash(&data[pos..]);
+        prev[pos % WINDOW] = head[h];
+        // Positions are stored plus one so that 0 means empty.
+        head[h] = pos + 1;
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the most bytes that can be summed before b may overflow.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    b << 16 | a
+}
+
+/// Compresses `data` into a zlib stream.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    // 32K window with default compression, the header checksum makes it
+    // a multiple of 31.
+    let mut w = BitWriter::new(vec![0x78, 0x9C]);
+    w.write(1, 1);
+    w.write(1, 2);
+
+    let mut head = vec![0usize; 1 << HASH_BITS];
+    let mut prev = vec![0usize; WINDOW];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = ::std::cmp::min(MAX_MATCH, data.len() - pos);
+            let mut candidate = head[hash(&data[pos..])];
+            let mut chain = 0;
+            while candidate > 0 && chain < MAX_CHAIN {
+                let start = candidate - 1;
+                if pos - start > WINDOW - 1 {
+                    break;
+                }
+
+                let len = data[start..].iter().zip(&data[pos..pos + max_len]).take_while(|&(a, b)| a == b).count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - start;
+                    if len == max_len {
+                        break;
+                    }
+                }
+
+                let next = prev[start % WINDOW];
+                if next == 0 || next > start {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(&mut w, best_len, best_dist);
+            for p in pos..pos + best_len {
+                insert(data, &mut head, &mut prev, p);
+            }
+            pos += best_len;
+        } else {
+            write_literal(&mut w, data[pos] as u32);
+            insert(data, &mut head, &mut prev, pos);
+            pos += 1;
+        }
+    }
+    write_literal(&mut w, 256);
+
+    let mut out = w.finish();
+    let checksum = adler32(data);
+    out.extend_from_slice(&[(checksum >> 24) as u8, (checksum >> 16) as u8, (checksum >> 8) as u8, checksum as u8]);
+
+    out
+}
+
+#[cfg(test)]
+pub mod tests {
+    use ::seed::Rng;
+    use super::*;
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> BitReader<'a> {
+        fn bit(&mut self) -> u32 {
+            let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+            self.pos += 1;
+            bit as u32
+        }
+
+        fn bits(&mut self, n: u32) -> u32 {
+            (0..n).fold(0, |v, i| v | self.bit() << i)
+        }
+
+        fn code(&mut self, n: u32) -> u32 {
+            (0..n).fold(0, |v, _| v << 1 | self.bit())
+        }
+
+        fn literal(&mut self) -> u32 {
+            let code = self.code(7);
+            if code < 24 {
+                return 256 + code;
+            }
+            let code = code << 1 | self.bit();
+            match code {
+                0x30..=0xBF => code - 0x30,
+                0xC0..=0xC7 => 280 + code - 0xC0,
+                _ => 144 + (code << 1 | self.bit()) - 0x190,
+            }
+        }
+    }
+
+    /// Decodes a zlib stream of fixed Huffman blocks, checking the header
+    /// and the Adler-32 trailer.
+    pub fn inflate(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(stream[0] & 0x0F, 8, "not deflate");
+        assert_eq!(((stream[0] as u32) << 8 | stream[1] as u32) % 31, 0, "bad header check");
+
+        let mut r = BitReader { data: &stream[2..stream.len() - 4], pos: 0 };
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let last = r.bits(1);
+            assert_eq!(r.bits(2), 1, "not a fixed Huffman block");
+
+            loop {
+                let symbol = r.literal();
+                if symbol < 256 {
+                    out.push(symbol as u8);
+                    continue;
+                }
+                if symbol == 256 {
+                    break;
+                }
+
+                let code = (symbol - 257) as usize;
+                let len = LENGTH_BASE[code] as usize + r.bits(LENGTH_EXTRA[code] as u32) as usize;
+                let code = r.code(5) as usize;
+                let dist = DIST_BASE[code] as usize + r.bits(DIST_EXTRA[code] as u32) as usize;
+                assert!(dist <= out.len() && dist <= WINDOW, "distance {} out of range", dist);
+                for _ in 0..len {
+                    let byte = out[out.len() - dist];
+                    out.push(byte);
+                }
+            }
+
+            if last == 1 {
+                break;
+            }
+        }
+        assert_eq!(r.pos / 8 + (r.pos % 8 != 0) as usize, stream.len() - 6, "trailing data");
+
+        let trailer = &stream[stream.len() - 4..];
+        let checksum = trailer.iter().fold(0u32, |v, &b| v << 8 | b as u32);
+        assert_eq!(checksum, adler32(&out), "Adler-32 mismatch");
+
+        out
+    }
+
+    fn round_trip(data: &[u8]) {
+        assert_eq!(inflate(&zlib(data)), data);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"abcabcabcabcabcabcabcabcabcabc");
+        round_trip(&(0..=255u8).collect::<Vec<_>>());
+        round_trip(&vec![7u8; 100000]);
+
+        let mut rng = Rng::new(5);
+        let noise: Vec<u8> = (0..50000).map(|_| rng.below(4) as u8).collect();
+        round_trip(&noise);
+
+        // A repeat further back than the window can't be matched.
+        let mut far = noise.clone();
+        far.extend_from_slice(&[9; 1000]);
+        far.extend_from_slice(&noise[..1000]);
+        round_trip(&far);
+    }
+
+    #[test]
+    fn runs_compress() {
+        // A maximal match per 258 bytes, a little under 2 bytes each.
+        let screen = vec![0u8; 160 * 210];
+        assert!(zlib(&screen).len() < screen.len() / 100);
+    }
+
+    #[test]
+    fn adler32_vectors() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"a"), 0x00620062);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+
+        // Long enough for the sums to wrap, against a reduction per byte.
+        let data = vec![0xFFu8; 100000];
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in &data {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        assert_eq!(adler32(&data), b << 16 | a);
+    }
+}
@@ -0,0 +1,257 @@
+//! Animated GIF encoding.
+//!
+//! Every frame gets its own 256 color table from `index_colors`.
+use std::collections::HashMap;
+use std::io::Write;
+use ::Error;
+use super::{ColorType,check_frame,index_colors};
+
+const MAX_CODE_SIZE: u32 = 12;
+const CLEAR: u16 = 256;
+const END: u16 = 257;
+
+/// Packs variable width LZW codes into GIF data sub-blocks.
+struct CodeWriter {
+    out: Vec<u8>,
+    acc: u32,
+    bits: u32,
+}
+
+impl CodeWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// LZW compresses 8 bit indices the way GIF expects.
+fn lzw(indices: &[u8]) -> Vec<u8> {
+    let mut w = CodeWriter { out: Vec::new(), acc: 0, bits: 0 };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = 9;
+    let mut next = END + 1;
+    w.write(CLEAR, size);
+
+    let mut iter = indices.iter();
+    let mut prefix = match iter.next() {
+        Some(&first) => first as u16,
+        None => {
+            w.write(END, size);
+            return w.finish();
+        },
+    };
+
+    for &index in iter {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        w.write(prefix, size);
+        table.insert((prefix, index), next);
+        next += 1;
+        // The decoder adds each code one step later than we do, so it
+        // widens once the code just added needs more bits.
+        if next - 1 == 1 << size && size < MAX_CODE_SIZE {
+            size += 1;
+        }
+        if next == 1 << MAX_CODE_SIZE {
+            w.write(CLEAR, size);
+            table.clear();
+            size = 9;
+            next = END + 1;
+        }
+        prefix = index as u16;
+    }
+
+    w.write(prefix, size);
+    w.write(END, size);
+
+    w.finish()
+}
+
+/// Streams frames into an animated GIF that loops forever.
+///
+/// GIF delays are in hundredths of a second, so frame times are rounded
+/// while keeping the total duration right. Many viewers slow down delays
+/// below 2/100 s, record at 50 fps or less for accurate playback.
+pub struct GifEncoder<W: Write> {
+    w: W,
+    width: u16,
+    height: u16,
+    color: ColorType,
+    fps: u32,
+    frames: u64,
+}
+
+impl<W: Write> GifEncoder<W> {
+    pub fn new(mut w: W, width: u32, height: u32, color: ColorType, fps: u32) -> Result<Self, Error> {
+        assert!(fps > 0, "GifEncoder fps must be positive");
+        if width > u16::max_value() as u32 || height > u16::max_value() as u32 {
+            return Err(Error::Format("GIF frames are limited to 65535 pixels per side".to_owned()));
+        }
+
+        try!(w.write_all(b"GIF89a"));
+        try!(w.write_all(&[width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8, 0, 0, 0]));
+        // The NETSCAPE2.0 extension with a loop count of 0, looping forever.
+        try!(w.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00"));
+
+        Ok(GifEncoder {
+            w: w,
+            width: width as u16,
+            height: height as u16,
+            color: color,
+            fps: fps,
+            frames: 0,
+        })
+    }
+
+    pub fn add_frame(&mut self, data: &[u8]) -> Result<(), Error> {
+        try!(check_frame(self.width as u32, self.height as u32, self.color, data));
+
+        let fps = self.fps as u64;
+        let start = (self.frames * 100 + fps / 2) / fps;
+        let end = ((self.frames + 1) * 100 + fps / 2) / fps;
+        let delay = (end - start) as u16;
+        self.frames += 1;
+
+        let (table, indices) = index_colors(self.color, data);
+        let w = &mut self.w;
+        // A graphic control extension keeping the previous frame underneath.
+        try!(w.write_all(&[0x21, 0xF9, 0x04, 0x04, delay as u8, (delay >> 8) as u8, 0, 0]));
+        try!(w.write_all(&[0x2C, 0, 0, 0, 0,
+                           self.width as u8, (self.width >> 8) as u8, self.height as u8, (self.height >> 8) as u8,
+                           0x87]));
+        try!(w.write_all(&table));
+        try!(w.write_all(&[8]));
+        for block in lzw(&indices).chunks(255) {
+            try!(w.write_all(&[block.len() as u8]));
+            try!(w.write_all(block));
+        }
+        try!(w.write_all(&[0]));
+
+        Ok(())
+    }
+
+    /// Writes the trailer and returns the writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        try!(self.w.write_all(&[0x3B]));
+        try!(self.w.flush());
+
+        Ok(self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::seed::Rng;
+    use super::*;
+    use super::super::ColorType;
+
+    /// Decodes LZW codes the way GIF readers do.
+    fn unlzw(data: &[u8]) -> Vec<u8> {
+        let mut pos = 0;
+        let mut read = |size: u32| -> u16 {
+            let mut code = 0;
+            for i in 0..size {
+                code |= (((data[pos / 8] >> (pos % 8)) & 1) as u16) << i;
+                pos += 1;
+            }
+            code
+        };
+
+        let mut dict: Vec<Vec<u8>> = (0..258).map(|i| vec![i as u8]).collect();
+        let mut size = 9;
+        let mut prev: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        loop {
+            let code = read(size);
+            if code == CLEAR {
+                dict.truncate(258);
+                size = 9;
+                prev = None;
+                continue;
+            }
+            if code == END {
+                return out;
+            }
+
+            let entry = if (code as usize) < dict.len() {
+                dict[code as usize].clone()
+            } else {
+                assert_eq!(code as usize, dict.len(), "code {} isn't in the table yet", code);
+                let mut entry = prev.clone().expect("first code after a clear isn't a literal");
+                let first = entry[0];
+                entry.push(first);
+                entry
+            };
+            out.extend_from_slice(&entry);
+
+            if let Some(mut prefix) = prev {
+                if dict.len() < 1 << MAX_CODE_SIZE {
+                    prefix.push(entry[0]);
+                    dict.push(prefix);
+                }
+            }
+            if dict.len() == 1 << size && size < MAX_CODE_SIZE {
+                size += 1;
+            }
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        assert_eq!(unlzw(&lzw(&[])), Vec::<u8>::new());
+        assert_eq!(unlzw(&lzw(&[5])), vec![5]);
+        assert_eq!(unlzw(&lzw(&[1, 1, 1, 1, 1, 1, 1])), vec![1; 7]);
+
+        // Enough distinct strings to widen the codes up to 12 bits and
+        // clear the table several times.
+        let mut rng = Rng::new(9);
+        let noise: Vec<u8> = (0..100000).map(|_| rng.below(16) as u8).collect();
+        assert_eq!(unlzw(&lzw(&noise)), noise);
+
+        let all: Vec<u8> = (0..20000).map(|i| (i * 7 % 256) as u8).collect();
+        assert_eq!(unlzw(&lzw(&all)), all);
+    }
+
+    #[test]
+    fn frames_are_delayed_to_keep_time() {
+        let mut gif = GifEncoder::new(Vec::new(), 2, 2, ColorType::Gray, 30).unwrap();
+        for i in 0..3u8 {
+            gif.add_frame(&[i; 4]).unwrap();
+        }
+        let data = gif.finish().unwrap();
+
+        assert_eq!(&data[..6], b"GIF89a");
+        assert_eq!(&data[6..10], &[2, 0, 2, 0]);
+        assert_eq!(data[data.len() - 1], 0x3B);
+
+        let delays: Vec<u16> = data.windows(8)
+            .filter(|w| w[..4] == [0x21, 0xF9, 0x04, 0x04])
+            .map(|w| w[4] as u16 | (w[5] as u16) << 8)
+            .collect();
+        assert_eq!(delays, vec![3, 4, 3]);
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        assert!(GifEncoder::new(Vec::new(), 70000, 1, ColorType::Gray, 30).is_err());
+
+        let mut gif = GifEncoder::new(Vec::new(), 2, 2, ColorType::Rgb, 30).unwrap();
+        assert!(gif.add_frame(&[0; 4]).is_err());
+    }
+}
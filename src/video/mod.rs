@@ -0,0 +1,325 @@
+//! Pure Rust image and video encoders for game screens.
+//!
+//! Unlike `Game::save_screen_png`, these don't need ALE to be built with PNG
+//! support and work on any buffer from `Game::screen` or `Game::screen_rgb`.
+//! `VideoRecorder` wraps a game and writes every Nth episode to a directory.
+use std::collections::HashMap;
+use std::fs::{self,File};
+use std::io::BufWriter;
+use std::ops::Deref;
+use std::path::{Path,PathBuf};
+use ::{Action,Error,Game,StepInfo};
+
+mod deflate;
+pub mod gif;
+pub mod png;
+pub mod y4m;
+
+pub use self::gif::GifEncoder;
+pub use self::png::{ApngEncoder,write_png};
+pub use self::y4m::Y4mWriter;
+
+/// How the pixels of a frame are stored.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ColorType {
+    /// One byte per pixel, e.g. `Game::screen`. Palette indices are written
+    /// as gray levels.
+    Gray,
+    /// Three bytes per pixel, e.g. `Game::screen_rgb`.
+    Rgb,
+}
+
+impl ColorType {
+    pub fn channels(&self) -> usize {
+        match *self {
+            ColorType::Gray => 1,
+            ColorType::Rgb => 3,
+        }
+    }
+}
+
+fn check_frame(width: u32, height: u32, color: ColorType, data: &[u8]) -> Result<(), Error> {
+    if width == 0 || height == 0 {
+        return Err(Error::Format("frames must not be empty".to_owned()));
+    }
+    if data.len() != width as usize * height as usize * color.channels() {
+        return Err(Error::Format(format!("frame has {} bytes, expected {}x{} pixels of {:?}",
+                                         data.len(), width, height, color)));
+    }
+
+    Ok(())
+}
+
+/// Maps a frame to indices into a table of 256 RGB colors, returning the
+/// table and the indices. ALE frames never use more than 128 colors, so they
+/// are mapped exactly; RGB frames with more than 256 colors fall back to a
+/// fixed 3-3-2 bit palette.
+pub fn index_colors(color: ColorType, data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    match color {
+        ColorType::Gray => {
+            let table = (0..256).flat_map(|v| vec![v as u8; 3]).collect();
+            (table, data.to_vec())
+        },
+        ColorType::Rgb => {
+            let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+            let mut table = Vec::new();
+            let mut indices = Vec::with_capacity(data.len() / 3);
+            for pixel in data.chunks(3) {
+                let rgb = [pixel[0], pixel[1], pixel[2]];
+                let next = colors.len();
+                if next == 256 && !colors.contains_key(&rgb) {
+                    return index_colors_332(data);
+                }
+                let index = *colors.entry(rgb).or_insert(next as u8);
+                if index as usize == next {
+                    table.extend_from_slice(&rgb);
+                }
+                indices.push(index);
+            }
+
+            table.resize(768, 0);
+            (table, indices)
+        },
+    }
+}
+
+fn index_colors_332(data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let table = (0..256u32).flat_map(|i| {
+        vec![((i >> 5) * 255 / 7) as u8, ((i >> 2 & 7) * 255 / 7) as u8, ((i & 3) * 255 / 3) as u8]
+    }).collect();
+    let indices = data.chunks(3).map(|p| (p[0] & 0xE0) | (p[1] & 0xE0) >> 3 | p[2] >> 6).collect();
+
+    (table, indices)
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum VideoFormat {
+    Gif,
+    Apng,
+    Y4m,
+    /// A directory of numbered PNG files per episode.
+    PngFrames,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VideoOptions {
+    pub format: VideoFormat,
+    /// Records episodes 0, N, 2N, ...
+    pub every: u64,
+    pub color: ColorType,
+    pub fps: u32,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        VideoOptions {
+            format: VideoFormat::Gif,
+            every: 1,
+            color: ColorType::Rgb,
+            fps: 30,
+        }
+    }
+}
+
+enum Sink {
+    Gif(GifEncoder<BufWriter<File>>),
+    Apng(ApngEncoder, PathBuf),
+    Y4m(Y4mWriter<BufWriter<File>>),
+    PngFrames(PathBuf, u64),
+}
+
+/// Wraps a game and records the screen after every step, and at the start,
+/// of every Nth episode to a video named `episode-<n>` in a directory.
+pub struct VideoRecorder {
+    game: Game,
+    dir: PathBuf,
+    options: VideoOptions,
+    width: u32,
+    height: u32,
+    episode: u64,
+    sink: Option<Sink>,
+    buf: Vec<u8>,
+    videos: Vec<PathBuf>,
+}
+
+impl VideoRecorder {
+    /// Starts recording with episode 0, creating `dir` if needed.
+    pub fn new<P: AsRef<Path>>(game: Game, dir: P, options: VideoOptions) -> Result<Self, Error> {
+        assert!(options.every > 0, "VideoRecorder must record every N episodes for some positive N");
+        try!(fs::create_dir_all(dir.as_ref()));
+
+        let (width, height) = game.screen_dimensions();
+        let mut recorder = VideoRecorder {
+            game: game,
+            dir: dir.as_ref().to_path_buf(),
+            options: options,
+            width: width as u32,
+            height: height as u32,
+            episode: 0,
+            sink: None,
+            buf: Vec::new(),
+            videos: Vec::new(),
+        };
+        try!(recorder.start_episode());
+
+        Ok(recorder)
+    }
+
+    /// Whether the current episode is being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    pub fn episode(&self) -> u64 {
+        self.episode
+    }
+
+    fn start_episode(&mut self) -> Result<(), Error> {
+        if self.episode % self.options.every != 0 {
+            return Ok(());
+        }
+
+        let name = format!("episode-{:06}", self.episode);
+        let (width, height, color, fps) = (self.width, self.height, self.options.color, self.options.fps);
+        let sink = match self.options.format {
+            VideoFormat::Gif => {
+                let path = self.dir.join(format!("{}.gif", name));
+                let file = BufWriter::new(try!(File::create(&path)));
+                self.videos.push(path);
+                Sink::Gif(try!(GifEncoder::new(file, width, height, color, fps)))
+            },
+            VideoFormat::Apng => {
+                let fps = if fps > u16::max_value() as u32 { u16::max_value() } else { fps as u16 };
+                Sink::Apng(ApngEncoder::new(width, height, color, fps), self.dir.join(format!("{}.png", name)))
+            },
+            VideoFormat::Y4m => {
+                let path = self.dir.join(format!("{}.y4m", name));
+                let file = BufWriter::new(try!(File::create(&path)));
+                self.videos.push(path);
+                Sink::Y4m(try!(Y4mWriter::new(file, width, height, color, fps)))
+            },
+            VideoFormat::PngFrames => {
+                let path = self.dir.join(name);
+                try!(fs::create_dir_all(&path));
+                self.videos.push(path.clone());
+                Sink::PngFrames(path, 0)
+            },
+        };
+        self.sink = Some(sink);
+
+        self.capture()
+    }
+
+    fn capture(&mut self) -> Result<(), Error> {
+        let sink = match self.sink {
+            Some(ref mut sink) => sink,
+            None => return Ok(()),
+        };
+
+        match self.options.color {
+            ColorType::Gray => self.game.screen_in_buf(&mut self.buf),
+            ColorType::Rgb => self.game.screen_rgb_in_buf(&mut self.buf),
+        }
+
+        match *sink {
+            Sink::Gif(ref mut gif) => gif.add_frame(&self.buf),
+            Sink::Apng(ref mut apng, _) => apng.add_frame(&self.buf),
+            Sink::Y4m(ref mut y4m) => y4m.add_frame(&self.buf),
+            Sink::PngFrames(ref dir, ref mut frame) => {
+                let mut file = BufWriter::new(try!(File::create(dir.join(format!("frame-{:06}.png", frame)))));
+                *frame += 1;
+                write_png(&mut file, self.width, self.height, self.options.color, &self.buf)
+            },
+        }
+    }
+
+    fn finish_episode(&mut self) -> Result<(), Error> {
+        match self.sink.take() {
+            Some(Sink::Gif(gif)) => gif.finish().map(|_| ()),
+            Some(Sink::Apng(apng, path)) => {
+                let mut file = BufWriter::new(try!(File::create(&path)));
+                try!(apng.finish(&mut file));
+                self.videos.push(path);
+                Ok(())
+            },
+            Some(Sink::Y4m(y4m)) => y4m.finish().map(|_| ()),
+            Some(Sink::PngFrames(..)) | None => Ok(()),
+        }
+    }
+
+    /// Performs an action, recording the screen after it if this episode
+    /// is being recorded.
+    pub fn step(&mut self, action: Action) -> Result<StepInfo, Error> {
+        let info = self.game.step(action);
+        try!(self.capture());
+
+        Ok(info)
+    }
+
+    /// Finishes the current video, if any, and resets the game.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        try!(self.finish_episode());
+        self.game.reset();
+        self.episode += 1;
+
+        self.start_episode()
+    }
+
+    /// Finishes the current video and returns the game and the paths of
+    /// all videos written.
+    pub fn finish(mut self) -> Result<(Game, Vec<PathBuf>), Error> {
+        try!(self.finish_episode());
+
+        Ok((self.game, self.videos))
+    }
+}
+
+impl Deref for VideoRecorder {
+    type Target=Game;
+
+    fn deref(&self) -> &Game {
+        &self.game
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gray_frames_index_themselves() {
+        let (table, indices) = index_colors(ColorType::Gray, &[0, 7, 255]);
+        assert_eq!(indices, vec![0, 7, 255]);
+        assert_eq!(table.len(), 768);
+        assert_eq!(&table[21..24], &[7, 7, 7]);
+    }
+
+    #[test]
+    fn rgb_frames_with_few_colors_are_exact() {
+        let rgb = [9, 8, 7, 1, 2, 3, 9, 8, 7];
+        let (table, indices) = index_colors(ColorType::Rgb, &rgb);
+        assert_eq!(indices, vec![0, 1, 0]);
+        assert_eq!(&table[..6], &[9, 8, 7, 1, 2, 3]);
+        assert!(table[6..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn rgb_frames_with_many_colors_fall_back_to_332() {
+        let rgb: Vec<u8> = (0..257u32).flat_map(|i| vec![i as u8, (i >> 8) as u8, 0]).collect();
+        let (table, indices) = index_colors(ColorType::Rgb, &rgb);
+        assert_eq!(table.len(), 768);
+        assert_eq!(indices.len(), 257);
+        // 0xE0 red is 3-3-2 index 0b111_000_00, stored as full red.
+        assert_eq!(indices[0xE0], 0xE0);
+        assert_eq!(&table[3 * 0xE0..3 * 0xE0 + 3], &[255, 0, 0]);
+        // Low bits are dropped, so a green of 1 maps to black.
+        assert_eq!(indices[256], 0);
+    }
+
+    #[test]
+    fn frames_are_checked() {
+        assert!(check_frame(2, 2, ColorType::Rgb, &[0; 12]).is_ok());
+        assert!(check_frame(2, 2, ColorType::Rgb, &[0; 4]).is_err());
+        assert!(check_frame(0, 2, ColorType::Gray, &[]).is_err());
+    }
+}
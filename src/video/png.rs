@@ -0,0 +1,285 @@
+//! PNG and animated PNG (APNG) encoding.
+use std::io::Write;
+use ::Error;
+use ::bytes::Crc32;
+use super::{ColorType,check_frame};
+use super::deflate;
+
+const SIGNATURE: &'static [u8] = b"\x89PNG\r\n\x1a\n";
+
+struct ChunkWriter {
+    crc: Crc32,
+}
+
+impl ChunkWriter {
+    fn new() -> Self {
+        ChunkWriter { crc: Crc32::new() }
+    }
+
+    fn write<W: Write>(&mut self, w: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<(), Error> {
+        self.crc.reset();
+        self.crc.update(kind);
+        self.crc.update(data);
+
+        try!(w.write_all(&be32(data.len() as u32)));
+        try!(w.write_all(kind));
+        try!(w.write_all(data));
+        try!(w.write_all(&be32(self.crc.finish())));
+
+        Ok(())
+    }
+}
+
+fn be32(v: u32) -> [u8; 4] {
+    [(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+}
+
+fn header(width: u32, height: u32, color: ColorType) -> Vec<u8> {
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&be32(width));
+    ihdr.extend_from_slice(&be32(height));
+    // 8 bits per sample, then the color type, default compression and
+    // filtering, and no interlacing.
+    let kind = match color {
+        ColorType::Gray => 0,
+        ColorType::Rgb => 2,
+    };
+    ihdr.extend_from_slice(&[8, kind, 0, 0, 0]);
+
+    ihdr
+}
+
+/// Filters each row with whichever of the None, Sub and Up filters gives the
+/// smallest sum of absolute differences, and compresses the result.
+fn compress(width: u32, height: u32, color: ColorType, data: &[u8]) -> Vec<u8> {
+    let bpp = color.channels();
+    let stride = width as usize * bpp;
+    let mut filtered = Vec::with_capacity((stride + 1) * height as usize);
+    let mut sub = vec![0u8; stride];
+    let mut up = vec![0u8; stride];
+
+    for (y, row) in data.chunks(stride).enumerate() {
+        for x in 0..stride {
+            let left = if x >= bpp { row[x - bpp] } else { 0 };
+            let above = if y > 0 { data[(y - 1) * stride + x] } else { 0 };
+            sub[x] = row[x].wrapping_sub(left);
+            up[x] = row[x].wrapping_sub(above);
+        }
+
+        let cost = |bytes: &[u8]| bytes.iter().map(|&b| (b as i8 as i16).abs() as u64).sum::<u64>();
+        let (filter, bytes) = [(0u8, row), (1, &sub[..]), (2, &up[..])].iter()
+            .cloned()
+            .min_by_key(|&(_, bytes)| cost(bytes))
+            .unwrap();
+        filtered.push(filter);
+        filtered.extend_from_slice(bytes);
+    }
+
+    deflate::zlib(&filtered)
+}
+
+/// Writes a single frame as a PNG. `data` holds `height` rows of `width`
+/// pixels, e.g. from `Game::screen_rgb`, or from `Game::screen` with
+/// `ColorType::Gray`, where palette indices are written as gray levels.
+pub fn write_png<W: Write>(w: &mut W, width: u32, height: u32, color: ColorType, data: &[u8]) -> Result<(), Error> {
+    try!(check_frame(width, height, color, data));
+
+    let mut chunks = ChunkWriter::new();
+    try!(w.write_all(SIGNATURE));
+    try!(chunks.write(w, b"IHDR", &header(width, height, color)));
+    try!(chunks.write(w, b"IDAT", &compress(width, height, color, data)));
+    try!(chunks.write(w, b"IEND", &[]));
+
+    Ok(())
+}
+
+/// Encodes frames into an animated PNG. APNG stores the number of frames
+/// before the first one, so compressed frames are kept in memory until
+/// `finish`.
+pub struct ApngEncoder {
+    width: u32,
+    height: u32,
+    color: ColorType,
+    fps: u16,
+    frames: Vec<Vec<u8>>,
+}
+
+impl ApngEncoder {
+    pub fn new(width: u32, height: u32, color: ColorType, fps: u16) -> Self {
+        assert!(fps > 0, "ApngEncoder fps must be positive");
+
+        ApngEncoder {
+            width: width,
+            height: height,
+            color: color,
+            fps: fps,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn add_frame(&mut self, data: &[u8]) -> Result<(), Error> {
+        try!(check_frame(self.width, self.height, self.color, data));
+        self.frames.push(compress(self.width, self.height, self.color, data));
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Writes the animation, looping forever. Fails if there are no frames.
+    pub fn finish<W: Write>(self, w: &mut W) -> Result<(), Error> {
+        if self.frames.is_empty() {
+            return Err(Error::Format("an APNG needs at least one frame".to_owned()));
+        }
+
+        let mut chunks = ChunkWriter::new();
+        try!(w.write_all(SIGNATURE));
+        try!(chunks.write(w, b"IHDR", &header(self.width, self.height, self.color)));
+
+        let mut actl = Vec::with_capacity(8);
+        actl.extend_from_slice(&be32(self.frames.len() as u32));
+        actl.extend_from_slice(&be32(0));
+        try!(chunks.write(w, b"acTL", &actl));
+
+        let mut sequence = 0u32;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let mut fctl = Vec::with_capacity(26);
+            fctl.extend_from_slice(&be32(sequence));
+            fctl.extend_from_slice(&be32(self.width));
+            fctl.extend_from_slice(&be32(self.height));
+            fctl.extend_from_slice(&be32(0));
+            fctl.extend_from_slice(&be32(0));
+            // A delay of 1/fps seconds, then no disposal and no blending.
+            fctl.extend_from_slice(&[0, 1, (self.fps >> 8) as u8, self.fps as u8, 0, 0]);
+            try!(chunks.write(w, b"fcTL", &fctl));
+            sequence += 1;
+
+            if i == 0 {
+                try!(chunks.write(w, b"IDAT", frame));
+            } else {
+                let mut fdat = Vec::with_capacity(4 + frame.len());
+                fdat.extend_from_slice(&be32(sequence));
+                fdat.extend_from_slice(frame);
+                try!(chunks.write(w, b"fdAT", &fdat));
+                sequence += 1;
+            }
+        }
+
+        try!(chunks.write(w, b"IEND", &[]));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bytes::Crc32;
+    use super::*;
+    use super::super::ColorType;
+    use super::super::deflate::tests::inflate;
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        bytes[at..at + 4].iter().fold(0, |v, &b| v << 8 | b as u32)
+    }
+
+    /// Splits a PNG into its chunks, checking the signature and each CRC.
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(&png[..8], SIGNATURE);
+
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32_at(png, pos) as usize;
+            let mut crc = Crc32::new();
+            crc.update(&png[pos + 4..pos + 8 + len]);
+            assert_eq!(u32_at(png, pos + 8 + len), crc.finish());
+
+            let mut kind = [0; 4];
+            kind.copy_from_slice(&png[pos + 4..pos + 8]);
+            chunks.push((kind, png[pos + 8..pos + 8 + len].to_vec()));
+            pos += 12 + len;
+        }
+
+        chunks
+    }
+
+    /// Undoes the None, Sub and Up filters.
+    fn unfilter(filtered: &[u8], stride: usize, bpp: usize) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        for (y, row) in filtered.chunks(stride + 1).enumerate() {
+            for x in 0..stride {
+                let left = if x >= bpp { out[y * stride + x - bpp] } else { 0 };
+                let above = if y > 0 { out[(y - 1) * stride + x] } else { 0 };
+                out.push(match row[0] {
+                    0 => row[1 + x],
+                    1 => row[1 + x].wrapping_add(left),
+                    2 => row[1 + x].wrapping_add(above),
+                    filter => panic!("unexpected filter {}", filter),
+                });
+            }
+        }
+
+        out
+    }
+
+    fn frame(width: usize, height: usize, channels: usize) -> Vec<u8> {
+        (0..width * height * channels).map(|i| (i * i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn png_round_trips() {
+        for &(color, kind) in &[(ColorType::Gray, 0), (ColorType::Rgb, 2)] {
+            let data = frame(7, 5, color.channels());
+            let mut png = Vec::new();
+            write_png(&mut png, 7, 5, color, &data).unwrap();
+
+            let chunks = chunks(&png);
+            let kinds: Vec<&[u8]> = chunks.iter().map(|chunk| &chunk.0[..]).collect();
+            assert_eq!(kinds, vec![&b"IHDR"[..], b"IDAT", b"IEND"]);
+            assert_eq!(chunks[0].1, vec![0, 0, 0, 7, 0, 0, 0, 5, 8, kind, 0, 0, 0]);
+            assert_eq!(unfilter(&inflate(&chunks[1].1), 7 * color.channels(), color.channels()), data);
+        }
+    }
+
+    #[test]
+    fn png_rejects_frames_of_the_wrong_size() {
+        assert!(write_png(&mut Vec::new(), 7, 5, ColorType::Rgb, &[0; 7 * 5]).is_err());
+        assert!(write_png(&mut Vec::new(), 0, 0, ColorType::Gray, &[]).is_err());
+    }
+
+    #[test]
+    fn apng_numbers_its_chunks() {
+        let apng = ApngEncoder::new(4, 3, ColorType::Gray, 30);
+        assert!(apng.finish(&mut Vec::new()).is_err());
+
+        let mut apng = ApngEncoder::new(4, 3, ColorType::Gray, 30);
+        let frames: Vec<Vec<u8>> = (0..3u8).map(|i| vec![i * 40; 12]).collect();
+        for frame in &frames {
+            apng.add_frame(frame).unwrap();
+        }
+        let mut png = Vec::new();
+        apng.finish(&mut png).unwrap();
+
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8]> = chunks.iter().map(|chunk| &chunk.0[..]).collect();
+        assert_eq!(kinds, vec![&b"IHDR"[..], b"acTL", b"fcTL", b"IDAT", b"fcTL", b"fdAT", b"fcTL", b"fdAT", b"IEND"]);
+        assert_eq!(chunks[1].1, vec![0, 0, 0, 3, 0, 0, 0, 0]);
+
+        // fcTL and fdAT share one sequence, IDAT isn't numbered.
+        let sequence: Vec<u32> = chunks.iter()
+            .filter(|chunk| &chunk.0 == b"fcTL" || &chunk.0 == b"fdAT")
+            .map(|chunk| u32_at(&chunk.1, 0))
+            .collect();
+        assert_eq!(sequence, vec![0, 1, 2, 3, 4]);
+        assert_eq!(&chunks[2].1[20..24], &[0, 1, 0, 30]);
+
+        assert_eq!(unfilter(&inflate(&chunks[3].1), 4, 1), frames[0]);
+        assert_eq!(unfilter(&inflate(&chunks[7].1[4..]), 4, 1), frames[2]);
+    }
+}
@@ -0,0 +1,95 @@
+//! Uncompressed YUV4MPEG2 (Y4M) video, which ffmpeg and most players read.
+use std::io::Write;
+use ::Error;
+use super::{ColorType,check_frame};
+
+/// Streams frames into a Y4M file. Gray frames are written as monochrome,
+/// RGB frames as full resolution 4:4:4 YCbCr with BT.601 coefficients.
+pub struct Y4mWriter<W: Write> {
+    w: W,
+    width: u32,
+    height: u32,
+    color: ColorType,
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut w: W, width: u32, height: u32, color: ColorType, fps: u32) -> Result<Self, Error> {
+        assert!(fps > 0, "Y4mWriter fps must be positive");
+
+        let colorspace = match color {
+            ColorType::Gray => "mono",
+            ColorType::Rgb => "444",
+        };
+        try!(write!(w, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C{}\n", width, height, fps, colorspace));
+
+        Ok(Y4mWriter {
+            w: w,
+            width: width,
+            height: height,
+            color: color,
+            planes: Vec::new(),
+        })
+    }
+
+    pub fn add_frame(&mut self, data: &[u8]) -> Result<(), Error> {
+        try!(check_frame(self.width, self.height, self.color, data));
+        try!(self.w.write_all(b"FRAME\n"));
+
+        match self.color {
+            ColorType::Gray => try!(self.w.write_all(data)),
+            ColorType::Rgb => {
+                let n = (self.width * self.height) as usize;
+                self.planes.resize(3 * n, 0);
+                for (i, pixel) in data.chunks(3).enumerate() {
+                    let (r, g, b) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+                    self.planes[i] = (16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0).round() as u8;
+                    self.planes[n + i] = (128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0).round() as u8;
+                    self.planes[2 * n + i] = (128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0).round() as u8;
+                }
+                try!(self.w.write_all(&self.planes));
+            },
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, Error> {
+        try!(self.w.flush());
+
+        Ok(self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ColorType;
+
+    #[test]
+    fn gray_frames_are_written_as_is() {
+        let mut y4m = Y4mWriter::new(Vec::new(), 2, 1, ColorType::Gray, 60).unwrap();
+        y4m.add_frame(&[1, 2]).unwrap();
+        y4m.add_frame(&[3, 4]).unwrap();
+
+        assert_eq!(y4m.finish().unwrap(), b"YUV4MPEG2 W2 H1 F60:1 Ip A1:1 Cmono\nFRAME\n\x01\x02FRAME\n\x03\x04".to_vec());
+    }
+
+    #[test]
+    fn rgb_frames_are_converted_to_planes() {
+        let mut y4m = Y4mWriter::new(Vec::new(), 3, 1, ColorType::Rgb, 30).unwrap();
+        y4m.add_frame(&[0, 0, 0, 255, 255, 255, 255, 0, 0]).unwrap();
+        let data = y4m.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W3 H1 F30:1 Ip A1:1 C444\nFRAME\n";
+        assert_eq!(&data[..header.len()], &header[..]);
+        // Black, white and red in studio swing BT.601.
+        assert_eq!(&data[header.len()..], &[16, 235, 81, 128, 128, 90, 128, 128, 240]);
+    }
+
+    #[test]
+    fn frames_of_the_wrong_size_are_rejected() {
+        let mut y4m = Y4mWriter::new(Vec::new(), 3, 1, ColorType::Rgb, 30).unwrap();
+        assert!(y4m.add_frame(&[0; 3]).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+//! Save game round trips. These need libale_c and a ROM, so they are
+//! ignored by default; run them with
+//!
+//! ```text
+//! ALE_TEST_ROM=path/to/pong.bin cargo test -- --ignored
+//! ```
+#![cfg(feature="rustc-serialize")]
+extern crate ale;
+extern crate rustc_serialize;
+
+use std::env;
+use std::sync::Mutex;
+use rustc_serialize::{Encodable,Encoder};
+use rustc_serialize::json;
+use ale::{binary,Action,Ale,Game};
+use ale::serialize::{GameDecoder,Rom};
+
+// Only one ALE may exist at a time.
+static ALE_LOCK: Mutex<()> = Mutex::new(());
+
+fn load_game() -> Game {
+    let rom = env::var("ALE_TEST_ROM").expect("set ALE_TEST_ROM to the path of a ROM");
+    let mut game = Ale::new().load_rom(&rom);
+    for i in 0..100 {
+        game.act(Action(i % 4));
+    }
+
+    game
+}
+
+/// The layout `Game::encode` wrote up to version 0.2.1: the ROM path, the
+/// `Rom` and the system state back to back.
+struct Unversioned<'a>(&'a Game);
+
+impl<'a> Encodable for Unversioned<'a> {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        let rom = self.0.rom();
+        try!(rom.rom_path.to_str().unwrap().encode(s));
+        try!(rom.encode(s));
+        self.0.clone_system_state().encode(s)
+    }
+}
+
+#[test]
+#[ignore]
+fn binary_round_trip() {
+    let _lock = ALE_LOCK.lock().unwrap();
+
+    let (bytes, ram, screen) = {
+        let game = load_game();
+        (binary::encode(&game).unwrap(), game.ram(), game.screen())
+    };
+
+    let decoder: GameDecoder = binary::decode(&bytes).unwrap();
+    assert_eq!(decoder.header.format_version, 1);
+    assert!(decoder.overrides().is_empty());
+
+    let game = decoder.decode_game();
+    assert_eq!(game.ram(), ram);
+    assert_eq!(game.screen(), screen);
+}
+
+#[test]
+#[ignore]
+fn json_round_trip() {
+    let _lock = ALE_LOCK.lock().unwrap();
+
+    let (text, ram) = {
+        let game = load_game();
+        (json::encode(&game).unwrap(), game.ram())
+    };
+
+    let game = json::decode::<GameDecoder>(&text).unwrap().decode_game();
+    assert_eq!(game.ram(), ram);
+}
+
+#[test]
+#[ignore]
+fn unversioned_files_are_read() {
+    let _lock = ALE_LOCK.lock().unwrap();
+
+    let (bytes, ram, rom) = {
+        let game = load_game();
+        (binary::encode(&Unversioned(&game)).unwrap(), game.ram(), game.rom())
+    };
+
+    let decoder: GameDecoder = binary::decode(&bytes).unwrap();
+    assert_eq!(decoder.header.format_version, 0);
+    assert_eq!(decoder.rom.data, rom.data);
+
+    let game = decoder.decode_game();
+    assert_eq!(game.ram(), ram);
+}
+
+#[test]
+#[ignore]
+fn rom_md5_is_checked() {
+    let _lock = ALE_LOCK.lock().unwrap();
+
+    let bytes = binary::encode(&load_game()).unwrap();
+
+    // Flip a bit in the last byte of the ROM data, just before the state.
+    let rom = binary::encode(&Rom::new(env::var("ALE_TEST_ROM").unwrap())).unwrap();
+    let start = bytes.windows(rom.len()).position(|w| w == &rom[..]).unwrap();
+    let mut corrupt = bytes.clone();
+    corrupt[start + rom.len() - 1] ^= 1;
+
+    assert!(binary::decode::<GameDecoder>(&corrupt).is_err());
+}